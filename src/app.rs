@@ -2,23 +2,77 @@
  * Handles Main Application Loop
  */
 
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(feature = "http")]
+use crate::http::Http;
+#[cfg(feature = "mpris")]
+use crate::mpris::Mpris;
+#[cfg(unix)]
+use crate::{attach::RemoteBackend, control::Control};
 use crate::{
-    audio::AudioHandler,
-    config::Config,
+    audio::{AudioHandler, PRELOAD_THRESHOLD},
+    config::{Config, Repeat, Shuffle},
     event::{AppEvent, Event, EventHandler},
-    files::{Playlist, SourceHandler, Track},
+    files::{Playlist, PlaylistKind, SourceHandler, Track},
+    keymap::Keymap,
+    loudness, playlist_file,
+    search::{Search, SearchKind},
+    session::Session,
+    ui::album_art::AlbumArtState,
 };
+use rand::seq::{IndexedRandom, SliceRandom};
+#[cfg(unix)]
+use ratatui::Terminal;
 use ratatui::{
     DefaultTerminal,
     crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
     widgets::ListState,
 };
 
+/*
+ * Statics
+ */
+
+/// Tracks in a row that can fail to open before playback stops
+const SKIP_LIMIT: usize = 10;
+
 /// Context for current list
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum CurrentList {
     Playlists,
     Tracks,
+    Queue,
+    Search,
+    Diagnostics,
+    /// Choosing a playlist file to add tracks to
+    Picker,
+    Prompt,
+}
+
+/// Text input or confirmation over the lists
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+    /// Why the last confirm failed
+    pub error: Option<String>,
+}
+
+/// What a prompt does once confirmed, playlist files are referenced by path
+#[derive(Clone, PartialEq)]
+pub enum PromptKind {
+    Create,
+    Rename(PathBuf),
+    Delete(PathBuf),
+    /// Why something outside a prompt failed, confirming just closes it
+    Failed(String),
+    /// Something that only partly worked, confirming just closes it
+    Warning(String),
 }
 
 /// Application.
@@ -28,16 +82,52 @@ pub struct App {
 
     /// Config
     pub config: Config,
+    keymap: Keymap,
 
     /// Handlers & Handlers
     pub source: SourceHandler,
     pub audio: AudioHandler,
     pub events: EventHandler,
+    /// `None` if the socket couldn't be bound
+    #[cfg(unix)]
+    control: Option<Control>,
+    /// Running as a daemon, quitting from a terminal only detaches it
+    #[cfg(unix)]
+    headless: bool,
+    /// Terminal attached to the daemon
+    #[cfg(unix)]
+    remote: Option<Terminal<RemoteBackend>>,
+    /// `None` without a session bus
+    #[cfg(feature = "mpris")]
+    mpris: Option<Mpris>,
+    /// `None` if the address couldn't be bound
+    #[cfg(feature = "http")]
+    http: Option<Http>,
 
     /// State Handling
     pub current_list: CurrentList,
     pub album_list_state: ListState,
     pub track_list_state: ListState,
+    pub queue_list_state: ListState,
+    pub search_list_state: ListState,
+    pub diagnostics_list_state: ListState,
+    pub album_art: AlbumArtState,
+
+    /// Tracks in the order they were played, newest last
+    pub history: Vec<Track>,
+    /// Shuffled track picked ahead of time so peeking is stable
+    shuffle_next: Option<Track>,
+
+    /// Library search
+    pub search: Search,
+
+    /// Playlist editing
+    pub prompt: Option<Prompt>,
+    pub picker_list_state: ListState,
+    /// Tracks waiting for a playlist to be picked
+    picker_tracks: Vec<Track>,
+    /// List to go back to once a popup closes
+    previous_list: CurrentList,
 }
 
 impl App {
@@ -45,8 +135,73 @@ impl App {
      * Primary Functions
      */
 
-    /// Constructs a new instance of [`App`].
-    pub fn new(source: SourceHandler, audio: AudioHandler, config: Config) -> Self {
+    /// Constructs a new instance of [`App`], restoring the session if given.
+    pub fn new(
+        source: SourceHandler,
+        audio: AudioHandler,
+        config: Config,
+        keymap: Keymap,
+        session: Option<Session>,
+    ) -> Self {
+        // Query terminal graphics before the event thread starts reading stdin
+        let album_art = AlbumArtState::new();
+        let mut app = Self::build(
+            source,
+            audio,
+            config,
+            keymap,
+            EventHandler::new(),
+            album_art,
+        );
+        // Another instance may own the socket, it keeps working without one
+        #[cfg(unix)]
+        {
+            app.control = app.events.serve_control(false).ok();
+        }
+
+        if let Some(session) = session {
+            app.restore_session(&session);
+        }
+
+        app
+    }
+
+    /// Constructs an [`App`] without a terminal, controlled through the socket & attached terminals.
+    #[cfg(unix)]
+    pub fn headless(
+        source: SourceHandler,
+        audio: AudioHandler,
+        config: Config,
+        keymap: Keymap,
+        session: Option<Session>,
+    ) -> color_eyre::Result<Self> {
+        let mut app = Self::build(
+            source,
+            audio,
+            config,
+            keymap,
+            EventHandler::headless(),
+            AlbumArtState::headless(),
+        );
+        // Nothing could reach it without the socket
+        app.control = Some(app.events.serve_control(true)?);
+        app.headless = true;
+
+        if let Some(session) = session {
+            app.restore_session(&session);
+        }
+
+        Ok(app)
+    }
+
+    fn build(
+        source: SourceHandler,
+        audio: AudioHandler,
+        config: Config,
+        keymap: Keymap,
+        events: EventHandler,
+        album_art: AlbumArtState,
+    ) -> Self {
         // Init Lists
         let mut album_list_state = ListState::default();
         album_list_state.select_first();
@@ -54,18 +209,60 @@ impl App {
         let mut track_list_state = ListState::default();
         track_list_state.select_first();
 
+        let mut queue_list_state = ListState::default();
+        queue_list_state.select_first();
+
+        // Killed players still save their session, it's only lost if this fails
+        #[cfg(unix)]
+        let _ = events.serve_signals();
+
+        // Playing without desktop integration beats not playing at all
+        #[cfg(feature = "mpris")]
+        let mpris = events.serve_mpris().ok();
+        #[cfg(feature = "http")]
+        let http = events.serve_http(&config.http_address).ok();
+        #[cfg(feature = "http")]
+        if let Some(http) = http.as_ref() {
+            http.set_library(&source);
+        }
+
         Self {
             quit: false,
 
             config,
+            keymap,
 
             source,
             audio,
-            events: EventHandler::new(),
+            events,
+            #[cfg(unix)]
+            control: None,
+            #[cfg(unix)]
+            headless: false,
+            #[cfg(unix)]
+            remote: None,
+            #[cfg(feature = "mpris")]
+            mpris,
+            #[cfg(feature = "http")]
+            http,
 
             current_list: CurrentList::Playlists,
             album_list_state,
             track_list_state,
+            queue_list_state,
+            search_list_state: ListState::default(),
+            diagnostics_list_state: ListState::default(),
+            album_art,
+
+            history: Vec::new(),
+            shuffle_next: None,
+
+            search: Search::default(),
+
+            prompt: None,
+            picker_list_state: ListState::default(),
+            picker_tracks: Vec::new(),
+            previous_list: CurrentList::Playlists,
         }
     }
 
@@ -78,6 +275,34 @@ impl App {
         Ok(())
     }
 
+    /// Run the application's main loop without a terminal, drawing to an attached one if any.
+    #[cfg(unix)]
+    pub fn run_headless(&mut self) -> color_eyre::Result<()> {
+        while !self.quit {
+            self.draw_remote();
+            self.handle_events()?;
+        }
+        Ok(())
+    }
+
+    /// Draws into the attached terminal, picking up a newly attached one first
+    #[cfg(unix)]
+    fn draw_remote(&mut self) {
+        // A new terminal replaces the one before
+        if let Some(backend) = self.control.as_ref().and_then(|v| v.take_attached()) {
+            self.remote = Terminal::new(backend).ok();
+        }
+
+        let mut terminal = match self.remote.take() {
+            Some(v) => v,
+            None => return,
+        };
+        // Fails once the terminal is gone or stops reading, either way it's detached
+        if terminal.draw(|frame| self.render(frame)).is_ok() {
+            self.remote = Some(terminal);
+        }
+    }
+
     /*
      * Handlers
      */
@@ -86,6 +311,8 @@ impl App {
         match self.events.next()? {
             // Tick
             Event::Tick => self.tick(),
+            // Library
+            Event::SourceChanged => self.refresh_source(),
             // Terminal
             Event::Crossterm(event) => match event {
                 ratatui::crossterm::event::Event::Key(key_event) => {
@@ -97,24 +324,114 @@ impl App {
             Event::App(app_event) => match app_event {
                 // Quit
                 AppEvent::Quit => self.quit(),
+                #[cfg(unix)]
+                AppEvent::Detach => self.remote = None,
 
                 // List
                 AppEvent::ListUp => self.handle_list_events(AppEvent::ListUp),
                 AppEvent::ListDown => self.handle_list_events(AppEvent::ListDown),
+                AppEvent::ListTop => self.handle_list_events(AppEvent::ListTop),
+                AppEvent::ListBottom => self.handle_list_events(AppEvent::ListBottom),
                 AppEvent::ListQueue => self.handle_list_events(AppEvent::ListQueue),
+                AppEvent::ListQueueNext => self.handle_list_events(AppEvent::ListQueueNext),
                 AppEvent::ListSelect => self.handle_list_events(AppEvent::ListSelect),
                 AppEvent::ListBack => self.handle_list_events(AppEvent::ListBack),
+                AppEvent::ListShowQueue => self.current_list = CurrentList::Queue,
+                AppEvent::ListShowDiagnostics => {
+                    self.diagnostics_list_state.select_first();
+                    self.current_list = CurrentList::Diagnostics;
+                }
+
+                // Search
+                AppEvent::SearchOpen => self.open_search(),
+                AppEvent::SearchInput(c) => {
+                    self.search.push(c);
+                    self.search_list_state.select_first();
+                }
+                AppEvent::SearchDelete => {
+                    self.search.pop();
+                    self.search_list_state.select_first();
+                }
+
+                // Playlist Files
+                AppEvent::PlaylistCreate => self.open_prompt(PromptKind::Create),
+                AppEvent::PlaylistRename => self.open_playlist_prompt(false),
+                AppEvent::PlaylistDelete => self.open_playlist_prompt(true),
+                AppEvent::PlaylistAdd => self.open_picker(),
+                AppEvent::PromptInput(c) => {
+                    if let Some(prompt) = self.prompt.as_mut() {
+                        prompt.input.push(c);
+                        prompt.error = None;
+                    }
+                }
+                AppEvent::PromptDelete => {
+                    if let Some(prompt) = self.prompt.as_mut() {
+                        prompt.input.pop();
+                        prompt.error = None;
+                    }
+                }
+
+                // Queue
+                AppEvent::QueueRemove => self.handle_queue_events(AppEvent::QueueRemove),
+                AppEvent::QueueMoveUp => self.handle_queue_events(AppEvent::QueueMoveUp),
+                AppEvent::QueueMoveDown => self.handle_queue_events(AppEvent::QueueMoveDown),
+                AppEvent::QueueClear => self.handle_queue_events(AppEvent::QueueClear),
+                AppEvent::QueueSave => self.handle_queue_events(AppEvent::QueueSave),
+                AppEvent::QueuePath(path, found) => {
+                    // Nobody to tell if the remote gave up waiting
+                    let _ = found.send(self.queue_path(&path));
+                }
+                AppEvent::QueueRemoveAt(index, key, removed) => {
+                    // The remote saw the queue a moment ago, it may have moved since
+                    let matches = self
+                        .audio
+                        .queue
+                        .iter()
+                        .nth(index)
+                        .is_some_and(|track| track.key() == key);
+                    if matches {
+                        self.audio.queue.remove(index);
+                        self.clamp_queue_selection();
+                    }
+                    // Nobody to tell if the remote gave up waiting
+                    let _ = removed.send(matches);
+                }
+                AppEvent::QueueClearAll => {
+                    self.audio.queue.clear();
+                    self.clamp_queue_selection();
+                }
 
                 // Playback
                 AppEvent::PlayTogle => self.audio.toggle_playing(),
-                AppEvent::PlayNext => self.audio.next(),
+                AppEvent::PlayResume => self.audio.set_paused(false),
+                AppEvent::PlayPause => self.audio.set_paused(true),
+                AppEvent::PlayNext => self.next(),
                 AppEvent::PlayPrevious => self.previous(),
                 AppEvent::PlaySeekForward => self.audio.seek_forward(),
                 AppEvent::PlaySeekBack => self.audio.seek_back(),
+                AppEvent::PlaySeekTo(position, answer) => {
+                    match (self.audio.seek_to(position), answer) {
+                        // Nobody to tell if the remote gave up waiting
+                        (result, Some(answer)) => {
+                            let _ = answer.send(result.map_err(|e| e.to_string()));
+                        }
+                        // MPRIS has no way to answer
+                        (Err(e), None) => {
+                            self.show_error(color_eyre::eyre::eyre!("Failed to seek: {e}"))
+                        }
+                        (Ok(_), None) => {}
+                    }
+                }
+                AppEvent::PlayCycleRepeat => self.config.set_repeat(self.config.repeat.cycle()),
+                AppEvent::PlayCycleShuffle => {
+                    self.config.set_shuffle(self.config.shuffle.cycle());
+                    self.shuffle_next = None;
+                }
 
                 // Volume
                 AppEvent::VolumeUp => self.audio.raise_volume(0.05, &mut self.config),
                 AppEvent::VolumeDown => self.audio.lower_volume(0.05, &mut self.config),
+                AppEvent::VolumeSet(volume) => self.audio.set_volume(volume, &mut self.config),
             },
         }
         Ok(())
@@ -122,38 +439,76 @@ impl App {
 
     /// Handles the key events and updates the state of [`App`].
     pub fn handle_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        // Keys come from an attached terminal, leaving it shouldn't stop the daemon
+        let is_ctrl_c = key_event.modifiers == KeyModifiers::CONTROL
+            && matches!(key_event.code, KeyCode::Char('c' | 'C'));
+        #[cfg(unix)]
+        if self.headless && is_ctrl_c {
+            self.events.send(AppEvent::Detach);
+            return Ok(());
+        }
+
+        // Typing goes to the search input
+        if self.current_list == CurrentList::Search {
+            return self.handle_search_key_event(key_event);
+        }
+        if self.current_list == CurrentList::Prompt {
+            return self.handle_prompt_key_event(key_event);
+        }
+
+        // Always quits, even if the keymap lost every other way out
+        if is_ctrl_c {
+            self.events.send(AppEvent::Quit);
+            return Ok(());
+        }
+
+        match self.keymap.resolve(key_event) {
+            #[cfg(unix)]
+            Some(AppEvent::Quit) if self.headless => self.events.send(AppEvent::Detach),
+            Some(event) => self.events.send(event),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Handles key events while searching
+    pub fn handle_search_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
         match key_event.code {
             // Quit
-            KeyCode::Char('q') => self.events.send(AppEvent::Quit),
             KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
                 self.events.send(AppEvent::Quit)
             }
 
-            // Volume
-            KeyCode::Up if key_event.modifiers == KeyModifiers::CONTROL => {
-                self.events.send(AppEvent::VolumeUp);
-            }
-            KeyCode::Down if key_event.modifiers == KeyModifiers::CONTROL => {
-                self.events.send(AppEvent::VolumeDown);
-            }
+            // Input
+            KeyCode::Char(c) => self.events.send(AppEvent::SearchInput(c)),
+            KeyCode::Backspace => self.events.send(AppEvent::SearchDelete),
 
-            // List
+            // Results
             KeyCode::Up => self.events.send(AppEvent::ListUp),
             KeyCode::Down => self.events.send(AppEvent::ListDown),
             KeyCode::Tab => self.events.send(AppEvent::ListQueue),
             KeyCode::Enter => self.events.send(AppEvent::ListSelect),
             KeyCode::Esc => self.events.send(AppEvent::ListBack),
+            _ => {}
+        }
+        Ok(())
+    }
 
-            // Playback
-            KeyCode::Char(' ') => self.events.send(AppEvent::PlayTogle),
-            KeyCode::Right if key_event.modifiers == KeyModifiers::CONTROL => {
-                self.events.send(AppEvent::PlayNext);
-            }
-            KeyCode::Left if key_event.modifiers == KeyModifiers::CONTROL => {
-                self.events.send(AppEvent::PlayPrevious);
+    /// Handles key events while typing a playlist name
+    pub fn handle_prompt_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        match key_event.code {
+            // Quit
+            KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.events.send(AppEvent::Quit)
             }
-            KeyCode::Right => self.events.send(AppEvent::PlaySeekForward),
-            KeyCode::Left => self.events.send(AppEvent::PlaySeekBack),
+
+            // Input
+            KeyCode::Char(c) => self.events.send(AppEvent::PromptInput(c)),
+            KeyCode::Backspace => self.events.send(AppEvent::PromptDelete),
+
+            // Confirm & Cancel
+            KeyCode::Enter => self.events.send(AppEvent::ListSelect),
+            KeyCode::Esc => self.events.send(AppEvent::ListBack),
             _ => {}
         }
         Ok(())
@@ -161,6 +516,16 @@ impl App {
 
     /// Handles events related to [`CurrentList`].
     pub fn handle_list_events(&mut self, event: AppEvent) {
+        // Only confirm & cancel, there is no list
+        if self.current_list == CurrentList::Prompt {
+            match event {
+                AppEvent::ListSelect => self.confirm_prompt(),
+                AppEvent::ListBack => self.close_popup(),
+                _ => {}
+            }
+            return;
+        }
+
         // Get context
         let (current_list, list_length) = match self.current_list {
             CurrentList::Playlists => {
@@ -174,10 +539,32 @@ impl App {
                 &mut self.track_list_state,
                 // Length
                 self.source
-                    .num_tracks_in_playlists(self.album_list_state.selected().unwrap()),
+                    .num_tracks_in_playlists(self.album_list_state.selected().unwrap_or(0)),
+            ),
+            CurrentList::Queue => (&mut self.queue_list_state, self.audio.queue.len()),
+            CurrentList::Search => (&mut self.search_list_state, self.search.results.len()),
+            CurrentList::Diagnostics => (
+                &mut self.diagnostics_list_state,
+                self.source.diagnostics().len(),
+            ),
+            CurrentList::Picker => (
+                &mut self.picker_list_state,
+                self.source.user_playlists().len(),
             ),
+            // Handled above
+            CurrentList::Prompt => return,
         };
 
+        // Nothing to move through (e.g. empty queue)
+        if list_length == 0
+            && matches!(
+                event,
+                AppEvent::ListUp | AppEvent::ListDown | AppEvent::ListTop | AppEvent::ListBottom
+            )
+        {
+            return;
+        }
+
         // Handle in context
         match event {
             // Up
@@ -190,15 +577,40 @@ impl App {
                 true => current_list.select(Some(0)),
                 false => current_list.select_next(),
             },
+            // Ends
+            AppEvent::ListTop => current_list.select(Some(0)),
+            AppEvent::ListBottom => current_list.select(Some(list_length - 1)),
             // Queue
             AppEvent::ListQueue => match self.current_list {
                 // Only works on tracks, can't queue playlist
-                CurrentList::Playlists => {}
+                CurrentList::Playlists
+                | CurrentList::Queue
+                | CurrentList::Diagnostics
+                | CurrentList::Picker
+                | CurrentList::Prompt => {}
+                CurrentList::Tracks => {
+                    if let Some(track) = self.selected_track() {
+                        self.audio
+                            .queue_track(&track)
+                            .expect("Failed to play track")
+                    }
+                }
+                CurrentList::Search => self.queue_search_result(),
+            },
+            // Queue to play next
+            AppEvent::ListQueueNext => match self.current_list {
+                CurrentList::Playlists
+                | CurrentList::Queue
+                | CurrentList::Search
+                | CurrentList::Diagnostics
+                | CurrentList::Picker
+                | CurrentList::Prompt => {}
                 CurrentList::Tracks => {
-                    let track = self.selected_track().unwrap().clone();
-                    self.audio
-                        .queue_track(&track)
-                        .expect("Failed to play track")
+                    if let Some(track) = self.selected_track() {
+                        self.audio
+                            .queue_track_next(&track)
+                            .expect("Failed to play track")
+                    }
                 }
             },
             // Select
@@ -207,30 +619,499 @@ impl App {
                     self.current_list = CurrentList::Tracks;
                 }
                 CurrentList::Tracks => {
-                    let track = self.selected_track().unwrap().clone();
-                    self.audio
-                        .play_track(&track, true)
-                        .expect("Failed to play track")
+                    if let Some(track) = self.selected_track() {
+                        self.try_play(&track, true);
+                    }
+                }
+                // Play straight away, skipping the rest of the queue
+                CurrentList::Queue => {
+                    let index = self.queue_list_state.selected().unwrap_or(0);
+                    if let Some(track) = self.audio.queue.remove(index) {
+                        self.try_play(&track, false);
+                        self.clamp_queue_selection();
+                    }
                 }
+                CurrentList::Search => self.select_search_result(),
+                CurrentList::Picker => self.pick_playlist(),
+                // Only for reading
+                CurrentList::Diagnostics | CurrentList::Prompt => {}
             },
             // Back
-            AppEvent::ListBack => self.current_list = CurrentList::Playlists,
+            AppEvent::ListBack => match self.current_list {
+                CurrentList::Picker => self.close_popup(),
+                _ => self.current_list = CurrentList::Playlists,
+            },
             // Only want list events
             _ => {}
         };
     }
 
+    /// Opens search, reading the library the first time
+    fn open_search(&mut self) {
+        self.search.build_index(&self.source);
+        self.search.reset();
+        self.search_list_state.select_first();
+        self.current_list = CurrentList::Search;
+    }
+
+    /// Plays the selected search hit, or opens it if it's a playlist
+    fn select_search_result(&mut self) {
+        let index = self.search_list_state.selected().unwrap_or(0);
+        let kind = match self.search.get(index) {
+            Some(v) => v.kind.clone(),
+            None => return,
+        };
+
+        match kind {
+            SearchKind::Playlist(id) => {
+                self.album_list_state.select(Some(id));
+                self.track_list_state.select_first();
+                self.current_list = CurrentList::Tracks;
+            }
+            SearchKind::Track(track) => {
+                self.try_play(&track, true);
+                self.current_list = CurrentList::Playlists;
+            }
+        }
+    }
+
+    /// Queues the selected search hit, a whole playlist if that's what it is
+    fn queue_search_result(&mut self) {
+        let index = self.search_list_state.selected().unwrap_or(0);
+        let kind = match self.search.get(index) {
+            Some(v) => v.kind.clone(),
+            None => return,
+        };
+
+        let tracks = match kind {
+            SearchKind::Playlist(id) => match self.source.playlists.get(&id) {
+                Some(playlist) => playlist.tracks(),
+                None => return,
+            },
+            SearchKind::Track(track) => vec![*track],
+        };
+
+        for track in tracks {
+            self.audio
+                .queue_track(&track)
+                .expect("Failed to queue track");
+        }
+    }
+
+    /// Handles editing the queue, only while it is focused
+    pub fn handle_queue_events(&mut self, event: AppEvent) {
+        // Same keys edit playlist files while their tracks are focused
+        if self.current_list == CurrentList::Tracks {
+            self.handle_playlist_edit_events(event);
+            return;
+        }
+        if self.current_list != CurrentList::Queue {
+            return;
+        }
+
+        let index = self.queue_list_state.selected().unwrap_or(0);
+        match event {
+            AppEvent::QueueRemove => {
+                self.audio.queue.remove(index);
+            }
+            AppEvent::QueueMoveUp => {
+                let index = self.audio.queue.move_up(index);
+                self.queue_list_state.select(Some(index));
+            }
+            AppEvent::QueueMoveDown => {
+                let index = self.audio.queue.move_down(index);
+                self.queue_list_state.select(Some(index));
+            }
+            AppEvent::QueueClear => self.audio.queue.clear(),
+            AppEvent::QueueSave => self.save_queue(),
+            // Only want queue events
+            _ => {}
+        }
+
+        self.clamp_queue_selection();
+    }
+
+    /// Saves the queue as an M3U8 in the source directory, it then shows up as a playlist
+    fn save_queue(&mut self) {
+        if self.audio.queue.is_empty() {
+            return;
+        }
+
+        // Don't overwrite earlier saves
+        let mut path = self.source.path.join("Queue.m3u8");
+        let mut count = 1;
+        while path.exists() {
+            count += 1;
+            path = self.source.path.join(format!("Queue {count}.m3u8"));
+        }
+
+        let tracks: Vec<Track> = self.audio.queue.iter().cloned().collect();
+        match playlist_file::write_m3u8(&path, &tracks) {
+            Ok(skipped) => self.warn_skipped(skipped),
+            Err(e) => {
+                self.show_error(e.wrap_err("Failed to save queue"));
+                return;
+            }
+        }
+        self.refresh_source();
+    }
+
+    /// Queues a track by its path, or a whole playlist by its [`Playlist::key`]
+    ///
+    /// `false` if neither is in the library
+    fn queue_path(&mut self, path: &Path) -> bool {
+        let tracks = match self.source.find_track(path) {
+            Some(track) => vec![track],
+            None => match self.source.find_playlist(path) {
+                Some(playlist) => playlist.tracks(),
+                None => return false,
+            },
+        };
+
+        for track in tracks {
+            self.audio
+                .queue_track(&track)
+                .expect("Failed to queue track");
+        }
+        true
+    }
+
+    /// Removes & reorders tracks of the selected playlist file, saving it straight away
+    fn handle_playlist_edit_events(&mut self, event: AppEvent) {
+        let id = self.album_list_state.selected().unwrap_or(0);
+        let index = self.track_list_state.selected().unwrap_or(0);
+        let playlist = match self.source.playlists.get_mut(&id) {
+            Some(v) if v.kind == PlaylistKind::File => v,
+            _ => return,
+        };
+
+        match event {
+            AppEvent::QueueRemove => {
+                playlist.remove(index);
+            }
+            AppEvent::QueueMoveUp => {
+                let index = playlist.move_up(index);
+                self.track_list_state.select(Some(index));
+            }
+            AppEvent::QueueMoveDown => {
+                let index = playlist.move_down(index);
+                self.track_list_state.select(Some(index));
+            }
+            // Only want editing events
+            _ => return,
+        }
+
+        let result = playlist.save();
+        let len = playlist.tracks().len();
+
+        // Reread the file so the list shows what's actually saved
+        if let Err(e) = result {
+            self.show_error(e.wrap_err("Failed to save playlist"));
+            self.refresh_source();
+            return;
+        }
+
+        // Keep the selection in bounds
+        if index >= len {
+            self.track_list_state.select(Some(len.saturating_sub(1)));
+        }
+    }
+
+    /*
+     * Playlist Files
+     */
+
+    /// Opens a prompt over the current list
+    fn open_prompt(&mut self, kind: PromptKind) {
+        if !matches!(
+            self.current_list,
+            CurrentList::Playlists | CurrentList::Tracks | CurrentList::Picker
+        ) {
+            return;
+        }
+        // Creating from the picker goes back to where the picker was opened
+        if self.current_list != CurrentList::Picker {
+            self.previous_list = self.current_list;
+        }
+
+        let input = match &kind {
+            PromptKind::Rename(path) => path
+                .file_stem()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        self.prompt = Some(Prompt {
+            kind,
+            input,
+            error: None,
+        });
+        self.current_list = CurrentList::Prompt;
+    }
+
+    /// Renames or deletes the selected playlist, if it's a playlist file
+    fn open_playlist_prompt(&mut self, delete: bool) {
+        if !matches!(
+            self.current_list,
+            CurrentList::Playlists | CurrentList::Tracks
+        ) {
+            return;
+        }
+
+        let path = match self.selected_playlist() {
+            Some(v) if v.kind == PlaylistKind::File => v.path.clone(),
+            _ => return,
+        };
+
+        match delete {
+            true => self.open_prompt(PromptKind::Delete(path)),
+            false => self.open_prompt(PromptKind::Rename(path)),
+        }
+    }
+
+    /// Applies the prompt, keeping it open with the error if that fails
+    fn confirm_prompt(&mut self) {
+        let prompt = match self.prompt.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let result = match &prompt.kind {
+            PromptKind::Create => playlist_file::create(&self.source.path, &prompt.input),
+            PromptKind::Rename(path) => playlist_file::rename(path, &prompt.input),
+            PromptKind::Delete(path) => fs::remove_file(path)
+                .map(|_| path.clone())
+                .map_err(|e| e.into()),
+            // Nothing to apply
+            PromptKind::Failed(_) | PromptKind::Warning(_) => {
+                self.close_popup();
+                return;
+            }
+        };
+        let path = match result {
+            Ok(v) => v,
+            Err(e) => {
+                prompt.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        let kind = prompt.kind.clone();
+        self.prompt = None;
+        let tracks = std::mem::take(&mut self.picker_tracks);
+        self.refresh_source();
+
+        if let PromptKind::Delete(_) = kind {
+            self.current_list = CurrentList::Playlists;
+            return;
+        }
+
+        // Point at the new or renamed playlist
+        if let Some(playlist) = self.source.find_playlist(&path) {
+            self.album_list_state.select(Some(playlist.id));
+            self.track_list_state.select_first();
+        }
+        self.current_list = self.previous_list;
+
+        // Created from the picker, so it gets what was being added
+        if kind == PromptKind::Create && !tracks.is_empty() {
+            self.add_to_playlist(&path, tracks);
+        }
+    }
+
+    /// Opens the picker for the selected track, or the whole selected playlist
+    fn open_picker(&mut self) {
+        let tracks = match self.current_list {
+            CurrentList::Playlists => match self.selected_playlist() {
+                Some(playlist) => playlist.tracks(),
+                None => return,
+            },
+            CurrentList::Tracks => match self.selected_track() {
+                Some(track) => vec![track],
+                None => return,
+            },
+            _ => return,
+        };
+        if tracks.is_empty() {
+            return;
+        }
+
+        self.picker_tracks = tracks;
+        self.picker_list_state.select_first();
+        self.previous_list = self.current_list;
+        self.current_list = CurrentList::Picker;
+    }
+
+    /// Adds the waiting tracks to the picked playlist
+    fn pick_playlist(&mut self) {
+        let index = self.picker_list_state.selected().unwrap_or(0);
+        let path = match self.source.user_playlists().get(index) {
+            Some(playlist) => playlist.path.clone(),
+            None => return,
+        };
+
+        let tracks = std::mem::take(&mut self.picker_tracks);
+        self.close_popup();
+        self.add_to_playlist(&path, tracks);
+    }
+
+    /// Appends tracks to a playlist file, showing why if it can't be saved
+    fn add_to_playlist(&mut self, path: &Path, tracks: Vec<Track>) {
+        let id = match self.source.find_playlist(path) {
+            Some(v) => v.id,
+            None => return,
+        };
+
+        let result = match self.source.playlists.get_mut(&id) {
+            Some(playlist) => {
+                playlist.append(tracks);
+                playlist.save()
+            }
+            None => return,
+        };
+
+        // Reread the file so the list shows what's actually saved
+        match result {
+            Ok(0) => {}
+            Ok(skipped) => {
+                self.warn_skipped(skipped);
+                self.refresh_source();
+            }
+            Err(e) => {
+                self.show_error(e.wrap_err("Failed to add to playlist"));
+                self.refresh_source();
+            }
+        }
+    }
+
+    /// Closes the picker or prompt without doing anything
+    fn close_popup(&mut self) {
+        self.prompt = None;
+        self.picker_tracks.clear();
+        self.current_list = self.previous_list;
+    }
+
+    /// Shows why something failed over whatever list is open
+    fn show_error(&mut self, error: color_eyre::eyre::Error) {
+        self.show_message(PromptKind::Failed(format!("{error:#}")));
+    }
+
+    /// Warns about tracks a playlist file couldn't hold, they're split from a file by a cue sheet
+    fn warn_skipped(&mut self, skipped: usize) {
+        if skipped == 0 {
+            return;
+        }
+
+        self.show_message(PromptKind::Warning(format!(
+            "Left out {skipped} track(s) split from a file by a cue sheet, playlists can only hold whole files"
+        )));
+    }
+
+    /// Opens a prompt that only needs closing over whatever list is open
+    fn show_message(&mut self, kind: PromptKind) {
+        if self.current_list != CurrentList::Prompt {
+            self.previous_list = self.current_list;
+        }
+
+        self.prompt = Some(Prompt {
+            kind,
+            input: String::new(),
+            error: None,
+        });
+        self.current_list = CurrentList::Prompt;
+    }
+
+    /// Plays a track, reporting it instead if it can't be opened
+    ///
+    /// Returns false when it failed, see [`App::skip_unplayable`]
+    fn try_play(&mut self, track: &Track, set_primary: bool) -> bool {
+        match self.audio.play_track(track, set_primary) {
+            Ok(_) => true,
+            Err(e) => {
+                self.skip_unplayable(track, set_primary, e);
+                false
+            }
+        }
+    }
+
+    /// Reports a track that failed to open, moving past it so it isn't picked again
+    fn skip_unplayable(
+        &mut self,
+        track: &Track,
+        set_primary: bool,
+        error: color_eyre::eyre::Error,
+    ) {
+        // Otherwise the rest of the playlist would carry on from before it
+        if set_primary {
+            self.audio.primary_track = Some(track.clone());
+        }
+        // Shuffle doesn't pick played tracks
+        if self.history.last().map(|track| track.key()) != Some(track.key()) {
+            self.history.push(track.clone());
+        }
+
+        self.show_error(error.wrap_err(format!("Failed to play `{}`", track.path.display())));
+    }
+
+    /// Plays whatever comes next, skipping tracks that can't be opened
+    fn play_next_track(&mut self) {
+        for _ in 0..SKIP_LIMIT {
+            let (next, set_primary) = match self.take_next_track() {
+                Some(v) => v,
+                None => break,
+            };
+            if self.try_play(&next, set_primary) {
+                return;
+            }
+        }
+
+        self.audio.current_track = None;
+    }
+
+    /// Keeps the queue selection in bounds after tracks are removed
+    fn clamp_queue_selection(&mut self) {
+        let len = self.audio.queue.len();
+        match self.queue_list_state.selected() {
+            Some(v) if v >= len => self.queue_list_state.select(Some(len.saturating_sub(1))),
+            _ => {}
+        }
+    }
+
+    /// Skips the current track, repeat one only applies to tracks ending on their own
+    fn next(&mut self) {
+        if self.config.repeat != Repeat::One {
+            self.audio.next();
+            return;
+        }
+
+        let next = match self.audio.pop_queue() {
+            Some(track) => Some((track, false)),
+            None => self.following_track().map(|track| (track, true)),
+        };
+        self.shuffle_next = None;
+
+        if let Some((track, set_primary)) = next {
+            self.try_play(&track, set_primary);
+        }
+    }
+
     /// Handles trying to play previous song
     fn previous(&mut self) {
-        if let Some(primary_track) = &self.audio.primary_track {
-            if primary_track.metadata.number > 1 {
-                self.audio
-                    .play_track(
-                        &self.previous_in_playlist(&primary_track).unwrap().clone(),
-                        true,
-                    )
-                    .unwrap();
+        // Walk back through what shuffle played
+        if self.config.shuffle != Shuffle::Off {
+            if self.history.len() > 1 {
+                self.history.pop();
+                let track = self.history.last().unwrap().clone();
+                self.try_play(&track, true);
             }
+            return;
+        }
+
+        if let Some(primary_track) = &self.audio.primary_track
+            && let Some(track) = self.previous_in_playlist(primary_track)
+        {
+            self.try_play(&track, true);
         }
     }
 
@@ -241,20 +1122,19 @@ impl App {
     pub fn selected_playlist(&self) -> Option<&Playlist> {
         self.source
             .playlists
-            .get(&self.album_list_state.selected().unwrap())
+            .get(&self.album_list_state.selected()?)
     }
 
     pub fn selected_track(&self) -> Option<Track> {
-        let playlist = self.selected_playlist();
+        let playlist = self.selected_playlist()?;
 
-        // Indexes of tracks start a 1
-        playlist
-            .unwrap()
-            .get((self.track_list_state.selected().unwrap() + 1) as u32)
+        // List items line up with tracks in play order
+        playlist.track_at(self.track_list_state.selected()?)
     }
 
-    pub fn track_to_playlist(&self, track: &Track) -> &Playlist {
-        self.source.playlists.get(&track.playlist_index).unwrap()
+    /// None once the track's playlist is gone from the library
+    pub fn track_to_playlist(&self, track: &Track) -> Option<&Playlist> {
+        self.source.playlists.get(&track.playlist_index)
     }
 
     /*
@@ -262,15 +1142,186 @@ impl App {
      */
 
     pub fn next_in_playlist(&self, track: &Track) -> Option<Track> {
-        let playlist = self.track_to_playlist(track);
-        // The number starts at 1 instead of 0 (how albums number), so just use it as the index for next
-        playlist.get((track.metadata.number + 1) as u32)
+        // Goes by position so it carries on to the next disc
+        let playlist = self.track_to_playlist(track)?;
+        playlist.track_at(playlist.position(track)? + 1)
     }
 
     pub fn previous_in_playlist(&self, track: &Track) -> Option<Track> {
-        let playlist = self.track_to_playlist(track);
-        // The number starts at 1 instead of 0 (how albums number), so subtract 2 to get previous
-        playlist.get((track.metadata.number - 1) as u32)
+        // Goes by position so it carries back to the previous disc
+        let playlist = self.track_to_playlist(track)?;
+        playlist.track_at(playlist.position(track)?.checked_sub(1)?)
+    }
+
+    /// The track to auto play next, queue first then the rest of the primary playlist
+    ///
+    /// The bool is whether it should become the primary track
+    pub fn next_track(&mut self) -> Option<(Track, bool)> {
+        // There is a queue
+        if let Some(track) = self.audio.queue.peek() {
+            return Some((track.clone(), false));
+        }
+
+        // Loop the current track
+        if self.config.repeat == Repeat::One
+            && let Some(current) = &self.audio.current_track
+        {
+            return Some((current.track.clone(), true));
+        }
+
+        self.following_track().map(|track| (track, true))
+    }
+
+    /// Same as [`App::next_track`] but removes it from the queue
+    pub fn take_next_track(&mut self) -> Option<(Track, bool)> {
+        let next = self.next_track();
+        if !self.audio.queue.is_empty() {
+            self.audio.pop_queue();
+        }
+
+        // Pick a new shuffled track next time
+        self.shuffle_next = None;
+        next
+    }
+
+    /// Next track after the primary one, shuffled if enabled
+    fn following_track(&mut self) -> Option<Track> {
+        let primary_track = self.audio.primary_track.clone()?;
+
+        if self.config.shuffle != Shuffle::Off {
+            // Pick is stale if it was played manually or is from another playlist
+            let stale = match &self.shuffle_next {
+                Some(next) => {
+                    self.history.iter().any(|track| track.key() == next.key())
+                        || (self.config.shuffle == Shuffle::Playlist
+                            && next.playlist_index != primary_track.playlist_index)
+                }
+                None => true,
+            };
+            if stale {
+                self.shuffle_next = self.pick_shuffled(&primary_track);
+            }
+
+            // Everything has been played, start over
+            if self.shuffle_next.is_none() && self.config.repeat == Repeat::Playlist {
+                self.history
+                    .retain(|track| track.key() == primary_track.key());
+                self.shuffle_next = self.pick_shuffled(&primary_track);
+            }
+
+            return self.shuffle_next.clone();
+        }
+
+        match self.next_in_playlist(&primary_track) {
+            Some(track) => Some(track),
+            // Back to the start of the playlist
+            None if self.config.repeat == Repeat::Playlist => {
+                self.track_to_playlist(&primary_track)?.track_at(0)
+            }
+            None => None,
+        }
+    }
+
+    /// Random track that hasn't been played yet
+    fn pick_shuffled(&self, primary_track: &Track) -> Option<Track> {
+        let mut rng = rand::rng();
+        let played: HashSet<PathBuf> = self.history.iter().map(|track| track.key()).collect();
+
+        let mut playlists: Vec<&Playlist> = match self.config.shuffle {
+            Shuffle::Library => self.source.playlists.values().collect(),
+            _ => vec![self.track_to_playlist(primary_track)?],
+        };
+        playlists.shuffle(&mut rng);
+
+        // Go through playlists in random order so the whole library isn't read at once
+        for playlist in playlists {
+            let unplayed: Vec<Track> = playlist
+                .tracks()
+                .into_iter()
+                .filter(|track| !played.contains(&track.key()))
+                .collect();
+
+            if let Some(track) = unplayed.choose(&mut rng) {
+                return Some(track.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Remembers tracks as they start playing for shuffle & previous
+    fn record_history(&mut self) {
+        let current = match &self.audio.current_track {
+            Some(v) => &v.track,
+            None => return,
+        };
+
+        if self.history.last().map(|track| track.key()) != Some(current.key()) {
+            self.history.push(current.clone());
+        }
+    }
+
+    /// How long to crossfade into a track, None if it should be gapless
+    pub fn crossfade_length(&self, next: &Track) -> Option<Duration> {
+        if self.config.crossfade <= 0.0 {
+            return None;
+        }
+
+        // Albums meant to flow into each other shouldn't be faded
+        if self.config.gapless_albums
+            && let Some(current) = &self.audio.current_track
+            && current.track.playlist_index == next.playlist_index
+        {
+            return None;
+        }
+
+        Some(Duration::from_secs_f32(self.config.crossfade))
+    }
+
+    /// Lines up the next track once the current one is close to the end
+    fn line_up_next(&mut self) {
+        let remaining = match self.audio.remaining() {
+            Some(v) => v,
+            None => return,
+        };
+
+        // Not close enough to the end for anything
+        let crossfade = Duration::from_secs_f32(self.config.crossfade.max(0.0));
+        if remaining > PRELOAD_THRESHOLD.max(crossfade) {
+            return;
+        }
+
+        let next = match self.next_track() {
+            Some((track, _)) => track,
+            None => return,
+        };
+
+        match self.crossfade_length(&next) {
+            Some(length) if remaining <= length => {
+                let (next, set_primary) = match self.take_next_track() {
+                    Some(v) => v,
+                    None => return,
+                };
+                // Cutting to it would fail the same way, move on to the one after it
+                if let Err(e) = self
+                    .audio
+                    .crossfade(&next, set_primary, length.min(remaining))
+                {
+                    self.skip_unplayable(&next, set_primary, e);
+                }
+            }
+            None if remaining <= PRELOAD_THRESHOLD => {
+                let (next, set_primary) = match self.take_next_track() {
+                    Some(v) => v,
+                    None => return,
+                };
+                // Tried again with the one after it next tick
+                if let Err(e) = self.audio.preload(&next, set_primary) {
+                    self.skip_unplayable(&next, set_primary, e);
+                }
+            }
+            _ => {}
+        }
     }
 
     /*
@@ -280,32 +1331,40 @@ impl App {
     /// Handles the tick event of the terminal
     pub fn tick(&mut self) {
         self.tick_audio();
+
+        #[cfg(unix)]
+        if let Some(control) = self.control.as_ref() {
+            control.update(&self.audio, &self.config);
+        }
+
+        #[cfg(feature = "mpris")]
+        if let Some(mpris) = self.mpris.as_mut() {
+            mpris.update(&self.audio);
+        }
+
+        #[cfg(feature = "http")]
+        if let Some(http) = self.http.as_mut() {
+            http.update(&self.audio, &self.config, &self.source);
+        }
     }
 
     pub fn tick_audio(&mut self) {
-        let primary_track = &self.audio.primary_track;
+        // The lined up track is now the one being heard
+        self.audio.advance();
 
-        // track has finished
+        // track has finished with nothing lined up
         if self.audio.sink.empty() {
-            // There is a queue
-            if self.audio.queue.len() > 0 {
-                let next = &self.audio.pop_queue().unwrap();
-                self.audio.play_track(next, false).unwrap();
-            }
-            // Play next in playlist if nothing in queue
-            else if primary_track.is_some() {
-                let primary_track = primary_track.clone().unwrap();
-                let primary_num = primary_track.metadata.number as usize;
-                if primary_num < self.track_to_playlist(&primary_track).tracks().len() {
-                    self.audio
-                        .play_track(
-                            &self.next_in_playlist(&primary_track).unwrap().clone(),
-                            true,
-                        )
-                        .unwrap();
-                }
+            // Only look for a next track once, shuffle can be expensive
+            if self.audio.current_track.is_some() || !self.audio.queue.is_empty() {
+                self.play_next_track();
             }
         }
+        // Append or crossfade the next track ahead of time so there's no gap
+        else {
+            self.line_up_next();
+        }
+        self.audio.tick_fade();
+        self.record_history();
 
         // Tick track progress
         if !self.audio.sink.is_paused() && self.audio.current_track.is_some() {
@@ -314,11 +1373,178 @@ impl App {
         }
     }
 
+    /*
+     * Library
+     */
+
+    /// Rescans the source directory, keeping the selection & playback pointed at the same files
+    pub fn refresh_source(&mut self) {
+        let selected_playlist = self.selected_playlist().map(|playlist| playlist.key());
+        let known: HashSet<PathBuf> = self
+            .source
+            .playlists
+            .values()
+            .flat_map(|playlist| playlist.iter().map(|track| track.path.clone()))
+            .collect();
+        if self.source.refresh().is_err() {
+            return;
+        }
+
+        // Only what's new needs analysing, the rest was handed over at startup
+        let added: Vec<Playlist> = self
+            .source
+            .playlists
+            .values()
+            .filter(|playlist| playlist.iter().any(|track| !known.contains(&track.path)))
+            .cloned()
+            .collect();
+        if !added.is_empty() {
+            loudness::analyze_later(added);
+        }
+        #[cfg(feature = "http")]
+        if let Some(http) = self.http.as_ref() {
+            http.set_library(&self.source);
+        }
+
+        // Playlist ids shift when folders are added or removed
+        let relink =
+            |source: &SourceHandler, track: &mut Track| match source.find_track(&track.key()) {
+                Some(v) => {
+                    *track = v;
+                    true
+                }
+                None => false,
+            };
+
+        // Removed tracks carry on playing, but nothing follows on from them
+        if let Some(current) = self.audio.current_track.as_mut()
+            && !relink(&self.source, &mut current.track)
+        {
+            current.track.orphan();
+        }
+        if let Some(upcoming) = self.audio.upcoming.as_mut()
+            && !relink(&self.source, &mut upcoming.track)
+        {
+            upcoming.track.orphan();
+            upcoming.set_primary = false;
+        }
+        if let Some(primary) = self.audio.primary_track.as_mut()
+            && !relink(&self.source, primary)
+        {
+            self.audio.primary_track = None;
+        }
+        self.audio.queue.retain(|track| relink(&self.source, track));
+        self.history.retain_mut(|track| relink(&self.source, track));
+        self.shuffle_next = None;
+        self.search.clear_index();
+
+        // Selection
+        let playlist = selected_playlist
+            .as_ref()
+            .and_then(|path| self.source.find_playlist(path));
+        match playlist {
+            Some(playlist) => {
+                let num_tracks = playlist.tracks().len();
+                self.album_list_state.select(Some(playlist.id));
+                if self.track_list_state.selected().unwrap_or(0) >= num_tracks {
+                    self.track_list_state.select_first();
+                }
+            }
+            None => {
+                self.album_list_state.select_first();
+                self.track_list_state.select_first();
+                if self.current_list == CurrentList::Tracks {
+                    self.current_list = CurrentList::Playlists;
+                }
+            }
+        }
+        self.clamp_queue_selection();
+    }
+
+    /*
+     * Session
+     */
+
+    /// Restores playback paused at the saved position, along with the queue & selection
+    pub fn restore_session(&mut self, session: &Session) {
+        // Selection
+        let playlist = session
+            .selected_playlist
+            .as_ref()
+            .and_then(|path| self.source.find_playlist(path));
+        if let Some(playlist) = playlist {
+            self.album_list_state.select(Some(playlist.id));
+
+            let num_tracks = playlist.tracks().len();
+            if let Some(index) = session.selected_track.filter(|i| *i < num_tracks) {
+                self.track_list_state.select(Some(index));
+            }
+        }
+
+        // Queue
+        for path in &session.queue {
+            if let Some(track) = self.source.find_track(path) {
+                self.audio.queue.append(track);
+            }
+        }
+
+        // Playback
+        self.audio.primary_track = session
+            .primary_track
+            .as_ref()
+            .and_then(|path| self.source.find_track(path));
+
+        let current = session
+            .current_track
+            .as_ref()
+            .and_then(|path| self.source.find_track(path));
+        // Loaded paused so nothing is heard before the seek
+        if let Some(track) = current
+            && self.audio.load_track(&track, false).is_ok()
+        {
+            let _ = self.audio.sink.try_seek(session.position);
+
+            // Progress only ticks while playing, so set it here
+            if let Some(current) = self.audio.current_track.as_mut() {
+                current.elapsed_duration = session.position;
+            }
+        }
+    }
+
+    /// Snapshot of playback to restore next run
+    pub fn session(&self) -> Session {
+        let mut queue: Vec<PathBuf> = Vec::new();
+
+        // A queued track already lined up in the sink hasn't been heard yet
+        if let Some(upcoming) = &self.audio.upcoming
+            && !upcoming.set_primary
+        {
+            queue.push(upcoming.track.key());
+        }
+        queue.extend(self.audio.queue.iter().map(|track| track.key()));
+
+        Session {
+            current_track: self
+                .audio
+                .current_track
+                .as_ref()
+                .map(|current| current.track.key()),
+            position: self.audio.sink.get_pos(),
+            primary_track: self.audio.primary_track.as_ref().map(|track| track.key()),
+            queue,
+
+            selected_playlist: self.selected_playlist().map(|playlist| playlist.key()),
+            selected_track: self.track_list_state.selected(),
+        }
+    }
+
     /*
      * Quit
      */
 
     pub fn quit(&mut self) {
+        // A read-only source directory shouldn't keep the player from closing
+        let _ = self.session().save();
         self.quit = true;
     }
 }
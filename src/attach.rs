@@ -0,0 +1,263 @@
+/*
+ * Handles terminals attached to a daemon
+ *
+ * The daemon draws frames off-screen & sends the changed cells, the attached terminal sends keys back
+ */
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{Error, bail};
+use ratatui::{
+    backend::{Backend, WindowSize},
+    buffer::Cell,
+    crossterm::event::{self, Event as CrosstermEvent, KeyEventKind},
+    layout::{Position, Size},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    control::{self, Command, Reply},
+    event::Event,
+    keymap,
+};
+
+/*
+ * Statics
+ */
+
+/// How long a frame may take to send before the terminal is dropped, a stalled one would stall playback
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/*
+ * Data
+ */
+
+/// Daemon to terminal
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Frame {
+    /// Cells that changed since the last frame
+    Cells(Vec<(u16, u16, Cell)>),
+    Clear,
+}
+
+/// Terminal to daemon, once attached
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Input {
+    /// Written like a binding, e.g. `ctrl+up`
+    Key(String),
+    Resize(u16, u16),
+}
+
+/// Draws into an attached terminal, used as the daemon's ratatui backend
+pub struct RemoteBackend {
+    stream: UnixStream,
+    /// Updated by the connection's thread when the terminal resizes
+    size: Arc<Mutex<Size>>,
+}
+
+/*
+ * Daemon
+ */
+
+/// Forwards input from an attached terminal until it disconnects, returns its backend to draw with
+pub fn accept(
+    stream: UnixStream,
+    size: Size,
+    sender: mpsc::Sender<Event>,
+) -> Result<RemoteBackend, Error> {
+    let size = Arc::new(Mutex::new(size));
+    let reader = BufReader::new(stream.try_clone()?);
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    let shared = size.clone();
+    thread::spawn(move || {
+        for line in reader.lines() {
+            let input = match line.map(|v| serde_json::from_str::<Input>(&v)) {
+                Ok(Ok(v)) => v,
+                Ok(Err(_)) => continue,
+                Err(_) => return,
+            };
+
+            match input {
+                Input::Key(name) => {
+                    if let Some(key_event) = keymap::parse_key(&name) {
+                        let _ = sender.send(Event::Crossterm(CrosstermEvent::Key(key_event)));
+                    }
+                }
+                Input::Resize(width, height) => {
+                    *shared.lock().unwrap() = Size::new(width, height);
+                }
+            }
+        }
+    });
+
+    Ok(RemoteBackend { stream, size })
+}
+
+impl RemoteBackend {
+    fn send(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut line = serde_json::to_string(frame)?;
+        line.push('\n');
+        self.stream.write_all(line.as_bytes())
+    }
+}
+
+impl Drop for RemoteBackend {
+    /// Closes the connection for both sides, the input thread holds a clone of it
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Backend for RemoteBackend {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let cells: Vec<(u16, u16, Cell)> =
+            content.map(|(x, y, cell)| (x, y, cell.clone())).collect();
+        match cells.is_empty() {
+            true => Ok(()),
+            false => self.send(&Frame::Cells(cells)),
+        }
+    }
+
+    // The attached terminal keeps its cursor hidden
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn get_cursor_position(&mut self) -> io::Result<Position> {
+        Ok(Position::ORIGIN)
+    }
+
+    fn set_cursor_position<P: Into<Position>>(&mut self, _position: P) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.send(&Frame::Clear)
+    }
+
+    fn size(&self) -> io::Result<Size> {
+        Ok(*self.size.lock().unwrap())
+    }
+
+    fn window_size(&mut self) -> io::Result<WindowSize> {
+        Ok(WindowSize {
+            columns_rows: self.size()?,
+            // Unknown, only used for images
+            pixels: Size::new(0, 0),
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/*
+ * Terminal
+ */
+
+/// Shows a running daemon in this terminal until it detaches or stops
+pub fn run() -> Result<(), Error> {
+    let stream = control::connect()?;
+
+    let mut terminal = ratatui::init();
+    let size = terminal.size()?;
+    let result = attach(stream, size, terminal.backend_mut());
+    ratatui::restore();
+
+    result
+}
+
+fn attach<B: Backend>(mut stream: UnixStream, size: Size, backend: &mut B) -> Result<(), Error> {
+    let mut line = serde_json::to_string(&Command::Attach {
+        width: size.width,
+        height: size.height,
+    })?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    backend.hide_cursor()?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut reply = String::new();
+    reader.read_line(&mut reply)?;
+    let reply: Reply = serde_json::from_str(&reply)?;
+    if !reply.ok {
+        bail!("{}", reply.error.unwrap_or_default());
+    }
+
+    // Frames are read on their own thread so input never waits on drawing
+    let (frames, received) = mpsc::channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    let reader_closed = closed.clone();
+    thread::spawn(move || {
+        for line in reader.lines() {
+            let frame = match line.map(|v| serde_json::from_str::<Frame>(&v)) {
+                Ok(Ok(v)) => v,
+                Ok(Err(_)) => continue,
+                Err(_) => break,
+            };
+            if frames.send(frame).is_err() {
+                break;
+            }
+        }
+        reader_closed.store(true, Ordering::Relaxed);
+    });
+
+    while !closed.load(Ordering::Relaxed) {
+        // Draw everything that came in
+        for frame in received.try_iter() {
+            match frame {
+                Frame::Cells(cells) => {
+                    backend.draw(cells.iter().map(|(x, y, cell)| (*x, *y, cell)))?
+                }
+                Frame::Clear => backend.clear()?,
+            }
+        }
+        backend.flush()?;
+
+        if !event::poll(Duration::from_millis(10))? {
+            continue;
+        }
+        let input = match event::read()? {
+            CrosstermEvent::Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                match keymap::key_name(key_event) {
+                    Some(name) => Input::Key(name),
+                    None => continue,
+                }
+            }
+            CrosstermEvent::Resize(width, height) => Input::Resize(width, height),
+            _ => continue,
+        };
+
+        let mut line = serde_json::to_string(&input)?;
+        line.push('\n');
+        // The daemon closing is noticed by the reader
+        if stream.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -4,26 +4,49 @@
 
 use std::{
     fs::{self},
+    io::BufReader,
     time::Duration,
 };
 
 use color_eyre::eyre::Error;
-use rodio::{OutputStream, Sink, Source};
+use rodio::{
+    ChannelCount, OutputStream, Sample, SampleRate, Sink, Source,
+    source::{Amplify, SeekError},
+};
+
+use crate::{
+    config::{Config, Normalization},
+    files::Track,
+    loudness,
+    queue::Queue,
+};
 
-use crate::{config::Config, files::Track};
+/// How long before the end of a track the next one is appended to the sink
+pub const PRELOAD_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Decoded file as played, limited to a cue track's part of it
+type TrackSource = Amplify<Span<rodio::Decoder<BufReader<fs::File>>>>;
 
 pub struct AudioHandler {
     /// Player
-    _stream_handle: OutputStream,
+    stream_handle: OutputStream,
     pub sink: Sink,
+    // Previous track's sink while it fades out
+    fading: Option<Fading>,
 
     // The most recent forced played track
     pub primary_track: Option<Track>,
     // Current track
     pub current_track: Option<CurrentTrack>,
 
+    // Track appended to the sink behind the current one
+    pub upcoming: Option<Upcoming>,
+
     /// Queue
-    pub queue: Vec<Track>,
+    pub queue: Queue,
+
+    /// Which ReplayGain values are applied to new tracks
+    pub normalization: Normalization,
 }
 
 pub struct CurrentTrack {
@@ -32,6 +55,22 @@ pub struct CurrentTrack {
     pub total_duration: Duration,
 }
 
+/// Outgoing sink during a crossfade
+struct Fading {
+    sink: Sink,
+    /// Position in the outgoing track when the fade started
+    start: Duration,
+    length: Duration,
+}
+
+/// A track already decoded & waiting in the sink
+pub struct Upcoming {
+    pub track: Track,
+    pub total_duration: Duration,
+    /// Whether it becomes the primary track once audible
+    pub set_primary: bool,
+}
+
 impl AudioHandler {
     pub fn new() -> Self {
         let stream_handle = rodio::OutputStreamBuilder::open_default_stream()
@@ -41,13 +80,17 @@ impl AudioHandler {
         sink.pause();
 
         return Self {
-            _stream_handle: stream_handle,
+            stream_handle,
             sink,
+            fading: None,
 
             primary_track: None,
             current_track: None,
+            upcoming: None,
+
+            queue: Queue::new(),
 
-            queue: Vec::new(),
+            normalization: Normalization::Off,
         };
     }
 
@@ -57,14 +100,31 @@ impl AudioHandler {
 
     /// Forced played tracks
     pub fn play_track(&mut self, track: &Track, set_primary: bool) -> Result<(), Error> {
-        let file = fs::File::open(&track.path)?;
-        let decoder = rodio::Decoder::try_from(file)?;
-        let total_duration = decoder.total_duration().unwrap();
+        self.start_track(track, set_primary, true)
+    }
+
+    /// Same as [`AudioHandler::play_track`] but left paused
+    pub fn load_track(&mut self, track: &Track, set_primary: bool) -> Result<(), Error> {
+        self.start_track(track, set_primary, false)
+    }
+
+    fn start_track(&mut self, track: &Track, set_primary: bool, play: bool) -> Result<(), Error> {
+        let (decoder, total_duration) = open_track(track, self.normalization)?;
 
-        // Clean up sink so it plays immediately
+        // Anything lined up gets thrown out, put queued tracks back so they aren't lost
+        if let Some(upcoming) = self.upcoming.take()
+            && !upcoming.set_primary
+        {
+            self.queue.insert_next(upcoming.track);
+        }
+
+        // Clean up sink so it plays immediately, clearing also pauses it
+        self.fading = None;
         self.sink.clear();
         self.sink.append(decoder);
-        self.sink.play();
+        if play {
+            self.sink.play();
+        }
 
         // Allows rest of album to auto play
         if set_primary {
@@ -81,9 +141,121 @@ impl AudioHandler {
         Ok(())
     }
 
+    /// Time left in the current track, None if the next track is already lined up
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.upcoming.is_some() || self.sink.len() != 1 {
+            return None;
+        }
+
+        self.current_track
+            .as_ref()
+            .map(|current| current.total_duration.saturating_sub(self.sink.get_pos()))
+    }
+
+    /// Decodes a track & appends it behind the current one so playback is gapless
+    pub fn preload(&mut self, track: &Track, set_primary: bool) -> Result<(), Error> {
+        let (decoder, total_duration) = open_track(track, self.normalization)?;
+        self.sink.append(decoder);
+
+        self.upcoming = Some(Upcoming {
+            track: track.clone(),
+            total_duration,
+            set_primary,
+        });
+
+        Ok(())
+    }
+
+    /// Starts a track on a new sink while the current one fades out
+    pub fn crossfade(
+        &mut self,
+        track: &Track,
+        set_primary: bool,
+        length: Duration,
+    ) -> Result<(), Error> {
+        let (decoder, total_duration) = open_track(track, self.normalization)?;
+
+        // Both sinks live on the same stream mixer
+        let sink = Sink::connect_new(self.stream_handle.mixer());
+        sink.set_volume(self.volume());
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+        sink.append(decoder.fade_in(length));
+
+        let outgoing = std::mem::replace(&mut self.sink, sink);
+        self.fading = Some(Fading {
+            start: outgoing.get_pos(),
+            sink: outgoing,
+            length,
+        });
+
+        if set_primary {
+            self.primary_track = Some(track.clone());
+        }
+
+        self.current_track = Some(CurrentTrack {
+            track: track.clone(),
+            elapsed_duration: Duration::default(),
+            total_duration,
+        });
+
+        Ok(())
+    }
+
+    /// Lowers the outgoing sink's volume, dropping it once the fade is done
+    pub fn tick_fade(&mut self) {
+        let fading = match &self.fading {
+            Some(v) => v,
+            None => return,
+        };
+
+        let progress = fading
+            .sink
+            .get_pos()
+            .saturating_sub(fading.start)
+            .as_secs_f32()
+            / fading.length.as_secs_f32();
+
+        if fading.length.is_zero() || progress >= 1.0 || fading.sink.empty() {
+            self.fading = None;
+        } else {
+            fading.sink.set_volume(self.volume() * (1.0 - progress));
+        }
+    }
+
+    /// Swaps to the upcoming track once the sink has moved onto it
+    ///
+    /// Returns true if the current track changed
+    pub fn advance(&mut self) -> bool {
+        // Still playing the current track
+        if self.upcoming.is_none() || self.sink.len() > 1 {
+            return false;
+        }
+
+        let upcoming = self.upcoming.take().unwrap();
+        if upcoming.set_primary {
+            self.primary_track = Some(upcoming.track.clone());
+        }
+
+        self.current_track = Some(CurrentTrack {
+            track: upcoming.track,
+            elapsed_duration: Duration::default(),
+            total_duration: upcoming.total_duration,
+        });
+
+        true
+    }
+
     /// tracks intentionally added to queue OR automatically added
     pub fn queue_track(&mut self, track: &Track) -> Result<(), Error> {
-        self.queue.push(track.clone());
+        self.queue.append(track.clone());
+        Ok(())
+    }
+
+    /// Queues a track to play before anything else queued
+    pub fn queue_track_next(&mut self, track: &Track) -> Result<(), Error> {
+        self.queue.insert_next(track.clone());
         Ok(())
     }
 
@@ -96,15 +268,28 @@ impl AudioHandler {
      */
 
     pub fn toggle_playing(&self) {
-        if self.sink.is_paused() {
-            self.sink.play();
-        } else {
-            self.sink.pause();
+        self.set_paused(!self.sink.is_paused());
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        let fading = self.fading.as_ref().map(|v| &v.sink);
+
+        for sink in std::iter::once(&self.sink).chain(fading) {
+            if paused {
+                sink.pause();
+            } else {
+                sink.play();
+            }
         }
     }
 
     pub fn next(&self) {
-        self.sink.clear();
+        // Jump straight into the lined up track if there is one
+        if self.upcoming.is_some() {
+            self.sink.skip_one();
+        } else {
+            self.sink.clear();
+        }
     }
 
     pub fn seek_forward(&self) {
@@ -123,6 +308,21 @@ impl AudioHandler {
         self.sink.try_seek(time).unwrap()
     }
 
+    /// Jumps to a position in the current track, ignored past its end
+    ///
+    /// Fails for formats that can't seek, the track keeps playing where it was
+    pub fn seek_to(&self, position: Duration) -> Result<(), SeekError> {
+        let current = match &self.current_track {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        if position > current.total_duration {
+            return Ok(());
+        }
+
+        self.sink.try_seek(position)
+    }
+
     pub fn seek_back(&self) {
         // If seeking before song starts
         if self.current_track.is_none() || self.sink.get_pos().as_secs() < 5 {
@@ -140,6 +340,13 @@ impl AudioHandler {
         self.sink.volume()
     }
 
+    pub fn set_volume(&self, volume: f32, config: &mut Config) {
+        self.sink.set_volume(round_vol(volume.clamp(0.0, 1.0)));
+
+        // Save to config
+        config.set_volume(self.volume());
+    }
+
     pub fn lower_volume(&self, amount: f32, config: &mut Config) {
         let volume = self.volume();
 
@@ -167,6 +374,156 @@ impl AudioHandler {
     }
 }
 
+/// Opens a decoder for a track with its gain applied, along with its length
+fn open_track(
+    track: &Track,
+    normalization: Normalization,
+) -> Result<(TrackSource, Duration), Error> {
+    let file = fs::File::open(&track.path)?;
+    let decoder = rodio::Decoder::try_from(file)?;
+
+    // Cue tracks only play their part of the file
+    let span = match &track.cue {
+        Some(cue) => Span::new(decoder, cue.start, cue.end),
+        None => Span::new(decoder, Duration::ZERO, None),
+    };
+    let total_duration = span
+        .total_duration()
+        .unwrap_or(track.metadata.total_duration);
+
+    Ok((
+        span.amplify(gain_factor(track, normalization)),
+        total_duration,
+    ))
+}
+
+/// Linear gain for a track, lowered if needed so the peak doesn't clip
+fn gain_factor(track: &Track, normalization: Normalization) -> f32 {
+    let metadata = &track.metadata;
+
+    // Use the other mode's values if the wanted ones are missing
+    let (gain, peak) = match normalization {
+        Normalization::Off => return 1.0,
+        Normalization::Track => (
+            metadata.track_gain.or(metadata.album_gain),
+            metadata.track_peak.or(metadata.album_peak),
+        ),
+        Normalization::Album => (
+            metadata.album_gain.or(metadata.track_gain),
+            metadata.album_peak.or(metadata.track_peak),
+        ),
+    };
+
+    // No tags, try the analysis cache (only has per track values)
+    let (gain, peak) = match gain {
+        Some(v) => (v, peak),
+        None => match loudness::lookup(&track.path) {
+            Some(v) => (v.gain(), Some(v.true_peak as f32)),
+            None => return 1.0,
+        },
+    };
+
+    let factor = 10f32.powf(gain / 20.0);
+
+    match peak {
+        Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+        _ => factor,
+    }
+}
+
 fn round_vol(input: f32) -> f32 {
     (input * 100.0).round() / 100.0
 }
+
+/*
+ * Span
+ */
+
+/// Plays part of a source, positions are relative to its start
+struct Span<S> {
+    inner: S,
+    start: Duration,
+    /// `None` plays to the end of the source
+    length: Option<Duration>,
+    /// Samples left before the end, counted across all channels
+    remaining: Option<u64>,
+}
+
+impl<S: Source> Span<S> {
+    fn new(mut inner: S, start: Duration, end: Option<Duration>) -> Self {
+        // Not every format seeks, those are read up to the start instead
+        if !start.is_zero() && inner.try_seek(start).is_err() {
+            let frames = (start.as_secs_f64() * inner.sample_rate() as f64) as usize;
+            let samples = frames * inner.channels() as usize;
+            inner.by_ref().take(samples).for_each(drop);
+        }
+
+        let length = end.map(|end| end.saturating_sub(start));
+        let mut span = Self {
+            inner,
+            start,
+            length,
+            remaining: None,
+        };
+        span.remaining = span.samples_after(Duration::ZERO);
+
+        span
+    }
+
+    /// Samples between a position & the end of the span
+    fn samples_after(&self, pos: Duration) -> Option<u64> {
+        let length = self.length?.saturating_sub(pos);
+        let per_second = self.inner.sample_rate() as u64 * self.inner.channels() as u64;
+
+        Some((length.as_secs_f64() * per_second as f64) as u64)
+    }
+}
+
+impl<S: Source> Iterator for Span<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        match self.remaining.as_mut() {
+            Some(0) => return None,
+            Some(remaining) => *remaining -= 1,
+            None => {}
+        }
+
+        self.inner.next()
+    }
+}
+
+impl<S: Source> Source for Span<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        match (self.inner.current_span_len(), self.remaining) {
+            (Some(len), Some(remaining)) => Some(len.min(remaining as usize)),
+            (None, Some(remaining)) => Some(remaining as usize),
+            (len, None) => len,
+        }
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self.length {
+            Some(v) => Some(v),
+            None => self
+                .inner
+                .total_duration()
+                .map(|total| total.saturating_sub(self.start)),
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(self.start + pos)?;
+        self.remaining = self.samples_after(pos);
+
+        Ok(())
+    }
+}
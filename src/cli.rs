@@ -0,0 +1,279 @@
+/*
+ * Handles the subcommands that play & query the library without the TUI
+ */
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{Error, bail};
+use serde::Serialize;
+
+#[cfg(unix)]
+use crate::control::{self, Command};
+use crate::{
+    audio::{AudioHandler, PRELOAD_THRESHOLD},
+    files::{Playlist, PlaylistKind, SourceHandler, Track},
+};
+
+/*
+ * Statics
+ */
+
+/// How often playback is checked on while playing from the command line
+const POLL_RATE: Duration = Duration::from_millis(100);
+
+/*
+ * Data
+ */
+
+/// An album or playlist file, as listed by `list albums`
+#[derive(Serialize, Clone, Debug)]
+pub struct AlbumEntry {
+    /// Key into [`SourceHandler::playlists`], changes between scans
+    #[serde(skip)]
+    pub id: usize,
+    pub title: String,
+    pub artists: String,
+    /// [`Playlist::key`], what `enqueue` accepts
+    pub path: PathBuf,
+    /// `album` or `file`
+    pub kind: String,
+    pub tracks: usize,
+}
+
+/// A track, as listed by `list tracks`
+#[derive(Serialize, Clone, Debug)]
+pub struct TrackEntry {
+    pub album: String,
+    pub disc: u32,
+    pub number: u32,
+    pub title: String,
+    pub artists: String,
+    /// Seconds
+    pub duration: f64,
+    /// [`Track::key`], what `enqueue` accepts
+    pub path: PathBuf,
+}
+
+/// What a target given on the command line refers to
+pub enum Target<'a> {
+    Playlist(&'a Playlist),
+    Tracks(Vec<Track>),
+}
+
+/*
+ * Functions
+ */
+
+/// Finds a target by path first, then by album title
+///
+/// Titles match case-insensitively, exactly or as part of a single album
+pub fn resolve<'a>(source: &'a SourceHandler, target: &str) -> Result<Target<'a>, Error> {
+    // Albums grouped from tags aren't a path on disk, their key is as listed
+    if let Some(playlist) = source.find_playlist(Path::new(target)) {
+        return Ok(Target::Playlist(playlist));
+    }
+
+    if let Ok(path) = fs::canonicalize(target) {
+        if let Some(playlist) = source.find_playlist(&path) {
+            return Ok(Target::Playlist(playlist));
+        }
+        if let Some(track) = source.find_track(&path) {
+            return Ok(Target::Tracks(vec![track]));
+        }
+
+        // A file split by a cue sheet is every track in it
+        let tracks: Vec<Track> = sorted_playlists(source)
+            .into_iter()
+            .flat_map(|playlist| playlist.tracks())
+            .filter(|track| track.path == path)
+            .collect();
+        return match tracks.is_empty() {
+            true => bail!("`{}` isn't in the library", path.display()),
+            false => Ok(Target::Tracks(tracks)),
+        };
+    }
+
+    let name = target.to_lowercase();
+    let albums = sorted_playlists(source);
+    if let Some(playlist) = albums
+        .iter()
+        .find(|playlist| playlist.title.to_lowercase() == name)
+    {
+        return Ok(Target::Playlist(playlist));
+    }
+
+    let matches: Vec<&Playlist> = albums
+        .into_iter()
+        .filter(|playlist| playlist.title.to_lowercase().contains(&name))
+        .collect();
+    match matches.as_slice() {
+        [] => bail!("No album or file named `{target}`"),
+        [playlist] => Ok(Target::Playlist(playlist)),
+        _ => {
+            let titles: Vec<&str> = matches.iter().map(|v| v.title.as_str()).collect();
+            bail!("`{target}` matches several albums: {}", titles.join(", "))
+        }
+    }
+}
+
+impl Target<'_> {
+    pub fn tracks(&self) -> Vec<Track> {
+        match self {
+            Target::Playlist(playlist) => playlist.tracks(),
+            Target::Tracks(tracks) => tracks.clone(),
+        }
+    }
+}
+
+/// Plays tracks in order until the last one finishes, printing each as it starts
+pub fn play(audio: &mut AudioHandler, tracks: Vec<Track>) -> Result<(), Error> {
+    let mut tracks = tracks.into_iter();
+    let first = match tracks.next() {
+        Some(v) => v,
+        None => bail!("Nothing to play"),
+    };
+    audio.play_track(&first, false)?;
+    print_playing(&first);
+
+    loop {
+        thread::sleep(POLL_RATE);
+
+        if audio.advance()
+            && let Some(current) = audio.current_track.as_ref()
+        {
+            print_playing(&current.track);
+        }
+
+        // Lined up ahead of time so there's no gap
+        if let Some(remaining) = audio.remaining()
+            && remaining <= PRELOAD_THRESHOLD
+            && let Some(next) = tracks.next()
+        {
+            audio.preload(&next, false)?;
+        }
+
+        if audio.sink.empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Queues a target in a running player
+#[cfg(unix)]
+pub fn enqueue(target: &Target) -> Result<(), Error> {
+    // A whole playlist is sent as one, the player knows it
+    let paths = match target {
+        Target::Playlist(playlist) => vec![playlist.key()],
+        Target::Tracks(tracks) => tracks.iter().map(|track| track.key()).collect(),
+    };
+
+    for path in paths {
+        let reply = control::send(&Command::Enqueue { path })?;
+        if !reply.ok {
+            bail!("{}", reply.error.unwrap_or_default());
+        }
+    }
+
+    Ok(())
+}
+
+/// Albums & playlist files in the order they're shown in the player
+pub fn albums(source: &SourceHandler) -> Vec<AlbumEntry> {
+    sorted_playlists(source)
+        .into_iter()
+        .map(|playlist| AlbumEntry {
+            id: playlist.id,
+            title: playlist.title.clone(),
+            artists: playlist.artists.clone(),
+            path: playlist.key(),
+            kind: match playlist.kind {
+                PlaylistKind::Album => "album",
+                PlaylistKind::File => "file",
+            }
+            .to_string(),
+            tracks: source.num_tracks_in_playlists(playlist.id),
+        })
+        .collect()
+}
+
+/// Tracks of a target, or of every album when not given
+pub fn tracks(source: &SourceHandler, target: Option<&Target>) -> Vec<TrackEntry> {
+    let tracks = match target {
+        Some(target) => target.tracks(),
+        // Playlist files only hold tracks already listed under their albums
+        None => sorted_playlists(source)
+            .into_iter()
+            .filter(|playlist| playlist.kind == PlaylistKind::Album)
+            .flat_map(|playlist| playlist.tracks())
+            .collect(),
+    };
+
+    tracks
+        .iter()
+        .map(|track| TrackEntry::new(source, track))
+        .collect()
+}
+
+impl TrackEntry {
+    pub fn new(source: &SourceHandler, track: &Track) -> Self {
+        Self {
+            album: source
+                .playlists
+                .get(&track.playlist_index)
+                .map(|playlist| playlist.title.clone())
+                .unwrap_or_default(),
+            disc: track.metadata.disc(),
+            number: track.metadata.number,
+            title: track.metadata.title.clone(),
+            artists: track.metadata.artists.clone(),
+            duration: track.metadata.total_duration.as_secs_f64(),
+            path: track.key(),
+        }
+    }
+}
+
+pub fn print_albums(albums: &[AlbumEntry]) {
+    for album in albums {
+        println!(
+            "{} - {} ({} tracks)",
+            album.artists, album.title, album.tracks
+        );
+    }
+}
+
+pub fn print_tracks(tracks: &[TrackEntry]) {
+    for track in tracks {
+        println!(
+            "{} | {:>2}. {} - {} ({})",
+            track.album,
+            track.number,
+            track.artists,
+            track.title,
+            format_duration(track.duration)
+        );
+    }
+}
+
+fn print_playing(track: &Track) {
+    println!(
+        "Playing {} - {}",
+        track.metadata.artists, track.metadata.title
+    );
+}
+
+/// `m:ss`
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn sorted_playlists(source: &SourceHandler) -> Vec<&Playlist> {
+    let mut sorted: Vec<&Playlist> = source.playlists.values().collect();
+    sorted.sort_by_key(|playlist| playlist.id);
+    sorted
+}
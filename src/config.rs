@@ -3,6 +3,7 @@
  */
 
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
     path::{Path, PathBuf},
     sync::OnceLock,
@@ -10,6 +11,8 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::{keymap, naming::DEFAULT_PATTERN};
+
 /*
  * Statics
  */
@@ -22,9 +25,110 @@ static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
  */
 
 /// Global Config
+///
+/// Missing fields fall back to their defaults so older configs still parse
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Config {
     pub volume: f32,
+    /// Seconds to fade between tracks, 0 disables it
+    pub crossfade: f32,
+    /// Never crossfade between consecutive tracks of the same playlist
+    pub gapless_albums: bool,
+    /// Which ReplayGain values to apply, off until opted into so levels don't change on upgrade
+    pub normalization: Normalization,
+    /// Auto-advance behaviour
+    pub repeat: Repeat,
+    pub shuffle: Shuffle,
+    /// Whether playlists are folders or albums grouped from tags
+    pub library: LibraryMode,
+    /// Folder layout playlists are read from, like `{artist}/{year} - {album}` or `tags`
+    pub naming: String,
+    /// Keys like `ctrl+up` or `gg` to event names, replaces the default bindings entirely
+    pub keymap: BTreeMap<String, String>,
+    /// Where the HTTP API listens when built with it, `0.0.0.0:<port>` to reach it from the network
+    pub http_address: String,
+}
+
+/// Loudness normalization mode
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    Off,
+    /// Every track at the same loudness
+    Track,
+    /// Keeps the loudness differences within an album
+    Album,
+}
+
+/// What happens when a track or playlist ends
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    Off,
+    /// Loop the current track
+    One,
+    /// Start the playlist over once it ends
+    Playlist,
+}
+
+/// Where shuffled tracks are picked from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Shuffle {
+    Off,
+    /// Tracks from the primary track's playlist
+    Playlist,
+    /// Tracks from every playlist
+    Library,
+}
+
+/// How tracks are grouped into playlists
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LibraryMode {
+    /// Folders matching the naming scheme
+    Folders,
+    /// Album & album artist tags at any depth, ignoring the folder layout
+    Albums,
+}
+
+impl Repeat {
+    /// Next mode when cycling through them
+    pub fn cycle(self) -> Self {
+        match self {
+            Repeat::Off => Repeat::Playlist,
+            Repeat::Playlist => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Repeat::Off => "off",
+            Repeat::One => "one",
+            Repeat::Playlist => "all",
+        }
+    }
+}
+
+impl Shuffle {
+    /// Next mode when cycling through them
+    pub fn cycle(self) -> Self {
+        match self {
+            Shuffle::Off => Shuffle::Playlist,
+            Shuffle::Playlist => Shuffle::Library,
+            Shuffle::Library => Shuffle::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Shuffle::Off => "off",
+            Shuffle::Playlist => "album",
+            Shuffle::Library => "all",
+        }
+    }
 }
 
 impl Config {
@@ -78,6 +182,16 @@ impl Config {
         self.save();
     }
 
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+        self.save();
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: Shuffle) {
+        self.shuffle = shuffle;
+        self.save();
+    }
+
     /*
      * Cleanup
      */
@@ -93,6 +207,17 @@ impl Config {
 
 impl Default for Config {
     fn default() -> Self {
-        Self { volume: 1.0 }
+        Self {
+            volume: 1.0,
+            crossfade: 0.0,
+            gapless_albums: true,
+            normalization: Normalization::Off,
+            repeat: Repeat::Off,
+            shuffle: Shuffle::Off,
+            library: LibraryMode::Folders,
+            naming: DEFAULT_PATTERN.to_string(),
+            keymap: keymap::default_bindings(),
+            http_address: "127.0.0.1:8470".to_string(),
+        }
     }
 }
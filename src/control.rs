@@ -0,0 +1,476 @@
+/*
+ * Handles the control socket scripts & keybinds drive a running player through
+ *
+ * Each line sent is a JSON command like `{"command":"seek","offset":-10}`, each gets a JSON line back
+ * A daemon also accepts `attach`, after which the connection carries a terminal instead
+ *
+ * The socket is unix only, commands & replies are shared with the HTTP API
+ */
+
+#[cfg(unix)]
+use std::{
+    env,
+    fs::{self, Permissions},
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::Arc,
+    thread,
+};
+use std::{
+    path::PathBuf,
+    sync::{Mutex, mpsc},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use color_eyre::eyre::{Error, bail};
+#[cfg(unix)]
+use ratatui::layout::Size;
+use serde::{Deserialize, Serialize};
+
+#[cfg(unix)]
+use crate::attach::{self, RemoteBackend};
+use crate::{
+    audio::AudioHandler,
+    config::Config,
+    event::{AppEvent, Event},
+};
+
+/*
+ * Statics
+ */
+
+#[cfg(unix)]
+const SOCKET_NAME: &str = "tplayer.sock";
+/// How long commands the player answers wait for it, like `enqueue` looking the path up
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+ * Data
+ */
+
+/// Something to do, sent as `{"command": "<name>", ...}`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+    /// Seconds into the track, or relative to where it is when `offset` is given
+    Seek {
+        position: Option<f64>,
+        offset: Option<f64>,
+    },
+    /// Between 0 & 1
+    Volume {
+        level: f32,
+    },
+    /// A track, or every track of a playlist, by the keys `list` prints
+    Enqueue {
+        path: PathBuf,
+    },
+    Status,
+    Quit,
+    /// Turns the connection into a terminal showing the daemon, see [`crate::attach`]
+    Attach {
+        width: u16,
+        height: u16,
+    },
+}
+
+/// Answer to a single command
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+/// What's playing, as answered to `status`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// `playing`, `paused` or `stopped`
+    pub state: String,
+    pub title: Option<String>,
+    pub artists: Option<String>,
+    pub album: Option<String>,
+    pub path: Option<PathBuf>,
+    /// Seconds
+    pub position: f64,
+    pub duration: f64,
+    pub volume: f32,
+    pub queue_len: usize,
+    pub repeat: String,
+    pub shuffle: String,
+}
+
+/// Listening socket, removed once dropped
+#[cfg(unix)]
+pub struct Control {
+    path: PathBuf,
+    status: Arc<Mutex<Status>>,
+    /// Terminal that attached since the daemon last looked
+    attached: Arc<Mutex<Option<RemoteBackend>>>,
+}
+
+/*
+ * Functions
+ */
+
+/// `$XDG_RUNTIME_DIR/tplayer.sock`, the temp dir when it isn't set
+#[cfg(unix)]
+pub fn socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_NAME),
+        // Shared with every user, who could make the socket first
+        _ => env::temp_dir().join(format!("tplayer-{}.sock", uid())),
+    }
+}
+
+/// Connects to a running player's socket
+#[cfg(unix)]
+pub fn connect() -> Result<UnixStream, Error> {
+    let path = socket_path();
+    check_owner(&path)?;
+
+    match UnixStream::connect(&path) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("No player listening on `{}`: {e}", path.display()),
+    }
+}
+
+/// Sends a command to a running player & waits for its reply
+#[cfg(unix)]
+pub fn send(command: &Command) -> Result<Reply, Error> {
+    let mut stream = connect()?;
+
+    let mut line = serde_json::to_string(command)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+#[cfg(unix)]
+impl Control {
+    /// Binds the socket & spawns a thread accepting connections, commands are sent as [`Event::App`]
+    ///
+    /// Terminals can only attach if `attachable`, a player with its own terminal isn't
+    pub fn spawn(sender: mpsc::Sender<Event>, attachable: bool) -> Result<Self, Error> {
+        let path = socket_path();
+
+        // A socket nobody answers on was left behind by a crash
+        check_owner(&path)?;
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                bail!(
+                    "Another player is already listening on `{}`",
+                    path.display()
+                );
+            }
+            fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+        let status = Arc::new(Mutex::new(Status::default()));
+        let attached = Arc::new(Mutex::new(None));
+
+        let shared = status.clone();
+        let shared_attached = attached.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+
+                // Clients may keep the connection open, don't block others
+                let sender = sender.clone();
+                let status = shared.clone();
+                let attached = match attachable {
+                    true => Some(shared_attached.clone()),
+                    false => None,
+                };
+                thread::spawn(move || handle_client(stream, sender, status, attached));
+            }
+        });
+
+        Ok(Self {
+            path,
+            status,
+            attached,
+        })
+    }
+
+    /// Terminal that attached since the last call
+    pub fn take_attached(&self) -> Option<RemoteBackend> {
+        self.attached.lock().unwrap().take()
+    }
+
+    /// Refreshes what `status` answers with
+    pub fn update(&self, audio: &AudioHandler, config: &Config) {
+        *self.status.lock().unwrap() = Status::read(audio, config);
+    }
+}
+
+impl Status {
+    pub fn read(audio: &AudioHandler, config: &Config) -> Self {
+        let current = audio.current_track.as_ref();
+        let track = current.map(|current| &current.track);
+
+        Self {
+            state: match (current, audio.sink.is_paused()) {
+                (None, _) => "stopped",
+                (Some(_), true) => "paused",
+                (Some(_), false) => "playing",
+            }
+            .to_string(),
+            title: track.map(|track| track.metadata.title.clone()),
+            artists: track.map(|track| track.metadata.artists.clone()),
+            album: track.and_then(|track| track.metadata.album.clone()),
+            path: track.map(|track| track.path.clone()),
+            position: current.map_or(0.0, |_| audio.sink.get_pos().as_secs_f64()),
+            duration: current.map_or(0.0, |current| current.total_duration.as_secs_f64()),
+            volume: audio.volume(),
+            queue_len: audio.queue.len(),
+            repeat: config.repeat.label().to_string(),
+            shuffle: config.shuffle.label().to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+            status: None,
+        }
+    }
+
+    pub fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(error),
+            status: None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn uid() -> u32 {
+    // Can't fail, only reads the process' credentials
+    unsafe { libc::getuid() }
+}
+
+/// Fails if something other than a socket of this user's is in the way, nothing there is fine
+///
+/// Anyone else's would see every command, or could pretend to be the player
+#[cfg(unix)]
+fn check_owner(path: &Path) -> Result<(), Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    if metadata.uid() != uid() || !metadata.file_type().is_socket() {
+        bail!(
+            "`{}` isn't a socket of yours, remove it or set XDG_RUNTIME_DIR",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Answers commands from one connection until it closes or attaches
+#[cfg(unix)]
+fn handle_client(
+    stream: UnixStream,
+    sender: mpsc::Sender<Event>,
+    status: Arc<Mutex<Status>>,
+    attached: Option<Arc<Mutex<Option<RemoteBackend>>>>,
+) {
+    let mut writer = match stream.try_clone() {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (reply, attach) = match serde_json::from_str::<Command>(&line) {
+            Ok(Command::Attach { width, height }) => match attached.is_some() {
+                true => (Reply::ok(), Some(Size::new(width, height))),
+                false => (
+                    Reply::error("Only a daemon can be attached to".to_string()),
+                    None,
+                ),
+            },
+            Ok(command) => (forward(command, &sender, &status), None),
+            Err(e) => (Reply::error(format!("Invalid command: {e}")), None),
+        };
+
+        let mut reply = serde_json::to_string(&reply).unwrap();
+        reply.push('\n');
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+
+        // The rest of the connection is the terminal's
+        if let (Some(size), Some(attached)) = (attach, attached.as_ref()) {
+            if let Ok(backend) = attach::accept(writer, size, sender) {
+                *attached.lock().unwrap() = Some(backend);
+            }
+            return;
+        }
+    }
+}
+
+/// Forwards a command into the app
+pub fn forward(command: Command, sender: &mpsc::Sender<Event>, status: &Mutex<Status>) -> Reply {
+    let event = match command {
+        Command::Play => AppEvent::PlayResume,
+        Command::Pause => AppEvent::PlayPause,
+        Command::Toggle => AppEvent::PlayTogle,
+        Command::Next => AppEvent::PlayNext,
+        Command::Previous => AppEvent::PlayPrevious,
+        Command::Seek { position, offset } => {
+            let target = match (position, offset) {
+                (Some(position), None) => position,
+                (None, Some(offset)) => status.lock().unwrap().position + offset,
+                _ => return Reply::error("Seek takes one of `position` or `offset`".to_string()),
+            };
+            let position = match Duration::try_from_secs_f64(target.max(0.0)) {
+                Ok(v) => v,
+                Err(_) => return Reply::error(format!("`{target}` isn't a position")),
+            };
+
+            // Some formats can't seek, only the player finds out
+            return match ask(sender, |answer| {
+                AppEvent::PlaySeekTo(position, Some(answer))
+            }) {
+                Ok(Ok(())) => Reply::ok(),
+                Ok(Err(e)) => Reply::error(format!("Failed to seek: {e}")),
+                Err(e) => Reply::error(e),
+            };
+        }
+        Command::Volume { level } => match (0.0..=1.0).contains(&level) {
+            true => AppEvent::VolumeSet(level),
+            false => return Reply::error("Volume has to be between 0 and 1".to_string()),
+        },
+        // Only the player knows what's in the library, wait for it to look
+        Command::Enqueue { path } => {
+            return match ask(sender, |found| AppEvent::QueuePath(path.clone(), found)) {
+                Ok(true) => Reply::ok(),
+                Ok(false) => Reply::error(format!("`{}` isn't in the library", path.display())),
+                Err(e) => Reply::error(e),
+            };
+        }
+        Command::Quit => AppEvent::Quit,
+        // Handled by the connection
+        Command::Attach { .. } => return Reply::ok(),
+        Command::Status => {
+            return Reply {
+                status: Some(status.lock().unwrap().clone()),
+                ..Reply::ok()
+            };
+        }
+    };
+
+    match sender.send(Event::App(event)) {
+        Ok(_) => Reply::ok(),
+        Err(_) => Reply::error("Player is shutting down".to_string()),
+    }
+}
+
+/// Sends an event the player answers through the given sender & waits for the answer
+///
+/// Fails with why when the answer never comes
+pub fn ask<T>(
+    sender: &mpsc::Sender<Event>,
+    event: impl FnOnce(mpsc::Sender<T>) -> AppEvent,
+) -> Result<T, String> {
+    let (answer, receiver) = mpsc::channel();
+    if sender.send(Event::App(event(answer))).is_err() {
+        return Err("Player is shutting down".to_string());
+    }
+
+    match receiver.recv_timeout(ANSWER_TIMEOUT) {
+        Ok(v) => Ok(v),
+        Err(_) => Err("Player didn't answer".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn only_our_sockets_are_used() {
+        let folder = std::env::temp_dir().join(format!("tplayer-control-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let socket = folder.join("player.sock");
+        let file = folder.join("player.txt");
+        let _listener = UnixListener::bind(&socket).unwrap();
+        fs::write(&file, "").unwrap();
+
+        let missing = check_owner(&folder.join("missing.sock"));
+        let ours = check_owner(&socket);
+        let not_socket = check_owner(&file);
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(missing.is_ok());
+        assert!(ours.is_ok());
+        assert!(not_socket.is_err());
+    }
+
+    #[test]
+    fn seeks_are_answered_once_applied() {
+        let (sender, receiver) = mpsc::channel();
+        let status = Mutex::new(Status::default());
+
+        // Stands in for the player, which can't seek this track
+        let player = std::thread::spawn(move || match receiver.recv() {
+            Ok(Event::App(AppEvent::PlaySeekTo(position, Some(answer)))) => {
+                answer.send(Err("unsupported".to_string())).unwrap();
+                position
+            }
+            _ => panic!("Expected a seek"),
+        });
+
+        let command = Command::Seek {
+            position: None,
+            offset: Some(30.0),
+        };
+        let reply = forward(command, &sender, &status);
+
+        assert_eq!(player.join().unwrap(), Duration::from_secs(30));
+        assert!(!reply.ok);
+        assert_eq!(reply.error.as_deref(), Some("Failed to seek: unsupported"));
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * Handles cue sheets for albums ripped to a single file
+ */
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::files;
+
+/*
+ * Statics
+ */
+
+/// Cue timestamps count frames, 75 to a second
+const FRAMES_PER_SECOND: u64 = 75;
+
+/*
+ * Data
+ */
+
+/// Part of a file that plays as its own track
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CueSpan {
+    /// Track number in the cue sheet, tells apart tracks sharing a file
+    pub index: u32,
+    pub start: Duration,
+    /// `None` plays to the end of the file
+    pub end: Option<Duration>,
+}
+
+/// Parsed `.cue` file
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+pub struct CueTrack {
+    /// Audio file the track is in
+    pub file: PathBuf,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub span: CueSpan,
+}
+
+/*
+ * Functions
+ */
+
+/// Whether a file is a cue sheet
+pub fn is_cue(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|v| v.eq_ignore_ascii_case("cue"))
+}
+
+/// Parses a cue sheet, files are resolved against the sheet's folder
+///
+/// `None` if it can't be read or has no tracks
+pub fn parse(path: &Path) -> Option<CueSheet> {
+    // Often written by windows tools in latin-1
+    let contents = String::from_utf8_lossy(&fs::read(path).ok()?).to_string();
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut sheet = CueSheet {
+        title: None,
+        performer: None,
+        tracks: Vec::new(),
+    };
+    let mut file = None;
+
+    for line in contents.lines().map(|v| v.trim()) {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match command.to_uppercase().as_str() {
+            "FILE" => file = Some(base.join(file_name(rest))),
+            "TRACK" => {
+                let file = match &file {
+                    Some(v) => v.clone(),
+                    None => continue,
+                };
+                let number = match rest.split_whitespace().next().and_then(|v| v.parse().ok()) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                sheet.tracks.push(CueTrack {
+                    file,
+                    title: None,
+                    performer: None,
+                    span: CueSpan {
+                        index: number,
+                        start: Duration::ZERO,
+                        end: None,
+                    },
+                });
+            }
+            // Before the first track these describe the album
+            "TITLE" => match sheet.tracks.last_mut() {
+                Some(track) => track.title = Some(quoted(rest)),
+                None => sheet.title = Some(quoted(rest)),
+            },
+            "PERFORMER" => match sheet.tracks.last_mut() {
+                Some(track) => track.performer = Some(quoted(rest)),
+                None => sheet.performer = Some(quoted(rest)),
+            },
+            // `INDEX 01` is where the track starts, `00` is the pregap before it
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                if parts.next() != Some("01") {
+                    continue;
+                }
+
+                let start = parts.next().and_then(timestamp);
+                if let (Some(track), Some(start)) = (sheet.tracks.last_mut(), start) {
+                    track.span.start = start;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Tracks end where the next one in the same file starts
+    for i in 1..sheet.tracks.len() {
+        if sheet.tracks[i].file == sheet.tracks[i - 1].file {
+            sheet.tracks[i - 1].span.end = Some(sheet.tracks[i].span.start);
+        }
+    }
+
+    match sheet.tracks.is_empty() {
+        true => None,
+        false => Some(sheet),
+    }
+}
+
+/// The file a cue track points at, sheets often name the `.wav` that was ripped before encoding
+pub fn find_audio_file(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+
+    let stem = path.file_stem()?;
+    fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|child| child.ok())
+        .map(|child| child.path())
+        .find(|candidate| candidate.file_stem() == Some(stem) && files::is_audio(candidate))
+}
+
+/// Value without surrounding quotes
+fn quoted(value: &str) -> String {
+    match value.strip_prefix('"') {
+        Some(v) => v.split('"').next().unwrap_or_default().to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// File name from `"name.flac" WAVE`, dropping the type after it
+fn file_name(value: &str) -> String {
+    if value.starts_with('"') {
+        return quoted(value);
+    }
+
+    match value.rsplit_once(' ') {
+        Some((name, _)) => name.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// `mm:ss:ff` into a duration
+fn timestamp(value: &str) -> Option<Duration> {
+    let mut parts = value.split(':').map(|v| v.parse::<u64>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+
+    let millis = (minutes * 60 + seconds) * 1000 + frames * 1000 / FRAMES_PER_SECOND;
+    Some(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn timestamps_count_frames() {
+        assert_eq!(timestamp("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(timestamp("01:02:15"), Some(Duration::from_millis(62_200)));
+        assert_eq!(timestamp("00:01:74"), Some(Duration::from_millis(1_986)));
+        assert_eq!(timestamp("1:02"), None);
+        assert_eq!(timestamp("aa:00:00"), None);
+    }
+
+    #[test]
+    fn file_names_drop_the_type() {
+        assert_eq!(file_name("\"Album Name.wav\" WAVE"), "Album Name.wav");
+        assert_eq!(file_name("album.flac WAVE"), "album.flac");
+        assert_eq!(file_name("album.flac"), "album.flac");
+    }
+
+    #[test]
+    fn tracks_end_where_the_next_starts() {
+        let folder = std::env::temp_dir().join(format!("tplayer-cue-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("album.cue");
+        fs::write(
+            &path,
+            "PERFORMER \"Artist\"\nTITLE \"Album\"\nFILE \"album.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Second\"\n    PERFORMER \"Guest\"\n    INDEX 00 02:59:00\n    INDEX 01 03:00:00\n",
+        )
+        .unwrap();
+
+        let sheet = parse(&path).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(sheet.title.as_deref(), Some("Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("Artist"));
+        assert_eq!(sheet.tracks.len(), 2);
+
+        let (first, second) = (&sheet.tracks[0], &sheet.tracks[1]);
+        assert_eq!(first.file, folder.join("album.wav"));
+        assert_eq!(first.title.as_deref(), Some("First"));
+        assert_eq!(
+            first.span,
+            CueSpan {
+                index: 1,
+                start: Duration::ZERO,
+                end: Some(Duration::from_secs(180)),
+            }
+        );
+        assert_eq!(second.performer.as_deref(), Some("Guest"));
+        assert_eq!(second.span.start, Duration::from_secs(180));
+        assert_eq!(second.span.end, None);
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * Handles checking the source directory for problems
+ */
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Error;
+use rodio::Decoder;
+use serde::Serialize;
+
+use crate::{
+    files::{self, Diagnostic, Playlist, Track},
+    library::LibraryIndex,
+    naming::NamingScheme,
+};
+
+/*
+ * Statics
+ */
+
+/// Files that commonly sit next to music & aren't worth reporting
+const COMPANION_EXTENSIONS: [&str; 14] = [
+    "jpg", "jpeg", "png", "webp", "gif", "cue", "log", "txt", "nfo", "m3u", "m3u8", "pls", "pdf",
+    "json",
+];
+
+/*
+ * Data
+ */
+
+/// A single problem found in the library
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub path: PathBuf,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Folder name doesn't fit the naming scheme
+    RejectedFolder,
+    DuplicateNumber,
+    MissingNumber,
+    UnsupportedExtension,
+    /// Tags or audio couldn't be read
+    Unreadable,
+    MissingCover,
+}
+
+/*
+ * Functions
+ */
+
+impl IssueKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            IssueKind::RejectedFolder => "Rejected folders",
+            IssueKind::DuplicateNumber => "Duplicate track numbers",
+            IssueKind::MissingNumber => "Missing track numbers",
+            IssueKind::UnsupportedExtension => "Unsupported files",
+            IssueKind::Unreadable => "Unreadable files",
+            IssueKind::MissingCover => "Missing cover art",
+        }
+    }
+}
+
+/// Walks the source directory like [`crate::files::SourceHandler::build`], collecting problems
+pub fn run(source: &Path, naming: &NamingScheme) -> Result<Vec<Issue>, Error> {
+    let mut issues = Vec::new();
+    // Read only, a doctor run shouldn't rewrite the index
+    let mut index = LibraryIndex::load(source);
+
+    fs::read_dir(source)?;
+    let (found, rejected) = naming.find_playlists(source);
+    for folder in rejected {
+        issues.push(Issue {
+            kind: IssueKind::RejectedFolder,
+            path: folder.path,
+            detail: folder.reason,
+        });
+    }
+
+    for (id, folder) in found.into_iter().enumerate() {
+        let playlist = Playlist::new(folder.title, folder.artists, folder.path, id);
+        let tracks = index.scan_playlist(&playlist);
+        check_numbers(&playlist, &tracks, &mut issues);
+        check_files(&playlist, &mut issues);
+        check_readable(&tracks, &mut issues);
+        check_cover(&playlist, &tracks, &mut issues);
+    }
+
+    Ok(issues)
+}
+
+/// Prints issues grouped by kind
+pub fn print(issues: &[Issue]) {
+    if issues.is_empty() {
+        println!("No problems found");
+        return;
+    }
+
+    let mut sorted: Vec<&Issue> = issues.iter().collect();
+    sorted.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.path.cmp(&b.path)));
+
+    let mut last_kind = None;
+    for issue in sorted {
+        if last_kind != Some(issue.kind) {
+            println!("\n{}:", issue.kind.label());
+            last_kind = Some(issue.kind);
+        }
+
+        println!("  {} ({})", issue.path.display(), issue.detail);
+    }
+
+    println!("\n{} problem(s) found", issues.len());
+}
+
+/// Duplicates & gaps in track numbers, along with tracks that had no number at all
+fn check_numbers(playlist: &Playlist, tracks: &[Track], issues: &mut Vec<Issue>) {
+    // Unnumbered tracks were given one after the max when scanned
+    for track in tracks
+        .iter()
+        .filter(|v| v.metadata.diagnostics.contains(&Diagnostic::Number))
+    {
+        issues.push(Issue {
+            kind: IssueKind::MissingNumber,
+            path: track.path.clone(),
+            detail: format!("no track number, placed at {}", track.metadata.number),
+        });
+    }
+
+    // Tracks are sorted by disc & number so duplicates are neighbours
+    for pair in tracks.windows(2) {
+        if pair[0].metadata.disc() == pair[1].metadata.disc()
+            && pair[0].metadata.number == pair[1].metadata.number
+        {
+            issues.push(Issue {
+                kind: IssueKind::DuplicateNumber,
+                path: pair[1].path.clone(),
+                detail: format!(
+                    "disc {} number {} is also used by {}",
+                    pair[1].metadata.disc(),
+                    pair[1].metadata.number,
+                    pair[0].path.display()
+                ),
+            });
+        }
+    }
+
+    // Each disc is numbered from 1
+    for disc in tracks
+        .iter()
+        .map(|v| v.metadata.disc())
+        .collect::<BTreeSet<u32>>()
+    {
+        let numbers: Vec<u32> = tracks
+            .iter()
+            .filter(|v| v.metadata.disc() == disc)
+            .map(|v| v.metadata.number)
+            .collect();
+        let max = numbers.iter().max().copied().unwrap_or(0);
+        let missing: Vec<String> = (1..=max)
+            .filter(|number| !numbers.contains(number))
+            .map(|number| number.to_string())
+            .collect();
+
+        if !missing.is_empty() {
+            issues.push(Issue {
+                kind: IssueKind::MissingNumber,
+                path: playlist.path.clone(),
+                detail: format!("disc {disc} has gaps at {}", missing.join(", ")),
+            });
+        }
+    }
+}
+
+/// Files that aren't playable & aren't something usually kept alongside music
+fn check_files(playlist: &Playlist, issues: &mut Vec<Issue>) {
+    let children = match fs::read_dir(&playlist.path) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    let mut paths: Vec<PathBuf> = children
+        .filter_map(|child| child.ok())
+        .filter(|child| child.file_type().is_ok_and(|v| v.is_file()))
+        .map(|child| child.path())
+        .filter(|path| !files::is_audio(path))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let extension = path
+            .extension()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        if !COMPANION_EXTENSIONS.contains(&extension.as_str()) {
+            issues.push(Issue {
+                kind: IssueKind::UnsupportedExtension,
+                path,
+                detail: match extension.is_empty() {
+                    true => "no extension".to_string(),
+                    false => format!(".{extension} isn't supported"),
+                },
+            });
+        }
+    }
+}
+
+/// Tracks lofty couldn't open or rodio can't decode
+fn check_readable(tracks: &[Track], issues: &mut Vec<Issue>) {
+    for (i, track) in tracks.iter().enumerate() {
+        // Cue tracks share a file, only check it once
+        if i > 0 && tracks[i - 1].path == track.path {
+            continue;
+        }
+
+        let detail = if track.metadata.diagnostics.contains(&Diagnostic::Unreadable) {
+            Diagnostic::Unreadable.describe().to_string()
+        } else {
+            match File::open(&track.path)
+                .map_err(Error::from)
+                .and_then(|file| Decoder::try_from(file).map(|_| ()).map_err(Error::from))
+            {
+                Ok(_) => continue,
+                Err(e) => format!("can't decode: {e}"),
+            }
+        };
+
+        issues.push(Issue {
+            kind: IssueKind::Unreadable,
+            path: track.path.clone(),
+            detail,
+        });
+    }
+}
+
+/// No cover file in the folder & no picture embedded in the first track
+fn check_cover(playlist: &Playlist, tracks: &[Track], issues: &mut Vec<Issue>) {
+    if files::cover_file(&playlist.path).is_some() {
+        return;
+    }
+    if tracks
+        .first()
+        .is_some_and(|track| files::embedded_cover(&track.path).is_some())
+    {
+        return;
+    }
+
+    issues.push(Issue {
+        kind: IssueKind::MissingCover,
+        path: playlist.path.clone(),
+        detail: "no cover file or embedded picture".to_string(),
+    });
+}
@@ -5,11 +5,19 @@
 use color_eyre::eyre::WrapErr;
 use ratatui::crossterm::event::{self, Event as CrosstermEvent};
 use std::{
+    path::PathBuf,
     sync::mpsc,
     thread,
     time::{Duration, Instant},
 };
 
+#[cfg(unix)]
+use signal_hook::{consts::SIGTERM, iterator::Signals};
+
+#[cfg(unix)]
+use crate::control::Control;
+use crate::watcher;
+
 /// The frequency at which tick events are emitted.
 const TICK_FPS: f64 = 30.0;
 
@@ -26,6 +34,10 @@ pub enum Event {
     ///
     /// These events are emitted by the terminal.
     Crossterm(CrosstermEvent),
+    /// Source directory changed.
+    ///
+    /// Emitted by the watcher thread once files have been added, removed or renamed.
+    SourceChanged,
     /// Application events.
     ///
     /// Use this event to emit custom events that are specific to your application.
@@ -39,24 +51,68 @@ pub enum Event {
 pub enum AppEvent {
     /// Quit
     Quit,
+    /// Drop the attached terminal, a daemon keeps playing
+    #[cfg(unix)]
+    Detach,
 
     /// List
     ListUp,
     ListDown,
+    ListTop,
+    ListBottom,
     ListQueue,
+    ListQueueNext,
     ListSelect,
     ListBack,
+    ListShowQueue,
+    ListShowDiagnostics,
+
+    /// Search
+    SearchOpen,
+    SearchInput(char),
+    SearchDelete,
+
+    /// Playlist Files
+    PlaylistCreate,
+    PlaylistRename,
+    PlaylistDelete,
+    PlaylistAdd,
+    PromptInput(char),
+    PromptDelete,
+
+    /// Queue
+    QueueRemove,
+    QueueMoveUp,
+    QueueMoveDown,
+    QueueClear,
+    QueueSave,
+    /// A track by its key, or every track of a playlist by its key
+    ///
+    /// Answers whether it was found, remotes reply once the library has been looked at
+    QueuePath(PathBuf, mpsc::Sender<bool>),
+    /// Same as their list counterparts, without the queue having to be focused
+    ///
+    /// Only removes the entry if it's still the track with the key, answering whether it was
+    QueueRemoveAt(usize, PathBuf, mpsc::Sender<bool>),
+    QueueClearAll,
 
     /// Playback
     PlayTogle,
+    PlayResume,
+    PlayPause,
     PlayNext,
     PlayPrevious,
     PlaySeekForward,
     PlaySeekBack,
+    /// Answers why it failed when given a sender, remotes reply once it's been tried
+    PlaySeekTo(Duration, Option<mpsc::Sender<Result<(), String>>>),
+    PlayCycleRepeat,
+    PlayCycleShuffle,
 
     /// Volume
     VolumeUp,
     VolumeDown,
+    VolumeSet(f32),
 }
 
 /// Terminal event handler.
@@ -71,8 +127,17 @@ pub struct EventHandler {
 impl EventHandler {
     /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events.
     pub fn new() -> Self {
+        Self::spawn(true)
+    }
+
+    /// Constructs an [`EventHandler`] that only emits ticks, for running without a terminal.
+    pub fn headless() -> Self {
+        Self::spawn(false)
+    }
+
+    fn spawn(read_terminal: bool) -> Self {
         let (sender, receiver) = mpsc::channel();
-        let actor = EventThread::new(sender.clone());
+        let actor = EventThread::new(sender.clone(), read_terminal);
         thread::spawn(|| actor.run());
         Self { sender, receiver }
     }
@@ -90,6 +155,44 @@ impl EventHandler {
         Ok(self.receiver.recv()?)
     }
 
+    /// Spawns a thread watching the source directory, emitting [`Event::SourceChanged`].
+    pub fn watch_source(&self, path: PathBuf) {
+        watcher::spawn(path, self.sender.clone());
+    }
+
+    /// Listens on the control socket, its commands are sent as [`Event::App`].
+    ///
+    /// Terminals attaching send their keys as [`Event::Crossterm`], only allowed if `attachable`.
+    #[cfg(unix)]
+    pub fn serve_control(&self, attachable: bool) -> color_eyre::Result<Control> {
+        Control::spawn(self.sender.clone(), attachable)
+    }
+
+    /// Sends [`AppEvent::Quit`] on `SIGTERM`, so being stopped by `kill` or a service manager saves the session.
+    #[cfg(unix)]
+    pub fn serve_signals(&self) -> color_eyre::Result<()> {
+        let mut signals = Signals::new([SIGTERM])?;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                let _ = sender.send(Event::App(AppEvent::Quit));
+            }
+        });
+        Ok(())
+    }
+
+    /// Serves the HTTP API at `address`, its requests are sent as [`Event::App`].
+    #[cfg(feature = "http")]
+    pub fn serve_http(&self, address: &str) -> color_eyre::Result<crate::http::Http> {
+        crate::http::Http::spawn(address, self.sender.clone())
+    }
+
+    /// Publishes the MPRIS interface, its method calls are sent as [`Event::App`].
+    #[cfg(feature = "mpris")]
+    pub fn serve_mpris(&self) -> color_eyre::Result<crate::mpris::Mpris> {
+        crate::mpris::Mpris::spawn(self.sender.clone())
+    }
+
     /// Queue an app event to be sent to the event receiver.
     ///
     /// This is useful for sending events to the event handler which will be processed by the next
@@ -105,12 +208,17 @@ impl EventHandler {
 struct EventThread {
     /// Event sender channel.
     sender: mpsc::Sender<Event>,
+    /// Whether to poll for crossterm events, a daemon has no terminal to read.
+    read_terminal: bool,
 }
 
 impl EventThread {
     /// Constructs a new instance of [`EventThread`].
-    fn new(sender: mpsc::Sender<Event>) -> Self {
-        Self { sender }
+    fn new(sender: mpsc::Sender<Event>, read_terminal: bool) -> Self {
+        Self {
+            sender,
+            read_terminal,
+        }
     }
 
     /// Runs the event thread.
@@ -126,6 +234,11 @@ impl EventThread {
                 last_tick = Instant::now();
                 self.send(Event::Tick);
             }
+            // without a terminal there's nothing to poll, just wait for the next tick
+            if !self.read_terminal {
+                thread::sleep(timeout);
+                continue;
+            }
             // poll for crossterm events, ensuring that we don't block the tick interval
             if event::poll(timeout).wrap_err("failed to poll for crossterm events")? {
                 let event = event::read().wrap_err("failed to read crossterm event")?;
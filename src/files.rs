@@ -3,23 +3,30 @@
  */
 
 use std::{
-    fs::{self, DirEntry},
-    num::NonZeroUsize,
-    path::PathBuf,
-    sync::{LazyLock, Mutex},
+    fs,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
-use color_eyre::eyre::{Error, bail};
+use color_eyre::eyre::{Error, eyre};
 use hashbrown::HashMap;
 use lofty::{
     file::{AudioFile, TaggedFileExt},
-    tag::Accessor,
+    picture::PictureType,
+    tag::{Accessor, ItemKey, Tag},
 };
-use lru::LruCache;
 use ratatui::{
     style::{Style, Stylize},
-    text::{Line, Text},
+    text::{Line, Span, Text},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::LibraryMode,
+    cue::{CueSheet, CueSpan, CueTrack},
+    library::LibraryIndex,
+    naming::NamingScheme,
+    playlist_file::{self, Unresolved},
 };
 
 /*
@@ -28,9 +35,15 @@ use ratatui::{
 
 /// Supported audio formats
 const AUDIO_EXTENSIONS: [&str; 7] = ["aac", "alac", "flac", "mp3", "ogg", "opus", "wav"];
-/// Cache of 5 most recent track lists
-static TRACK_CACHE: LazyLock<Mutex<LruCache<usize, Vec<Track>>>> =
-    LazyLock::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(10).unwrap())));
+/// Cover files checked in a playlist folder when a track has no embedded art
+const COVER_FILES: [&str; 6] = [
+    "cover.jpg",
+    "cover.png",
+    "folder.jpg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+];
 
 /*
  * Structs
@@ -41,18 +54,41 @@ pub struct SourceHandler {
     pub path: PathBuf,
     /// The playlists
     pub playlists: HashMap<usize, Playlist>,
+    /// How playlist folders are found, kept for rescans
+    naming: NamingScheme,
+    /// Whether playlists are folders or albums from tags
+    mode: LibraryMode,
 }
 
 #[derive(Default, Clone)]
 pub struct Playlist {
     /// Used to by tracks to reference playlist
     pub id: usize,
-    /// Second half of folder name
+    /// Album, from the folder name or tags
     pub title: String,
-    /// First half of folder name
+    /// Album artist(s), from the folder name or tags
     pub artists: String,
-    /// Path to folder
+    /// Path to folder, the folder holding every track when grouped by tags
+    ///
+    /// For playlist files, the file itself
     pub path: PathBuf,
+    pub kind: PlaylistKind,
+    /// Tracks sorted by disc & number, filled in from the library index
+    tracks: Vec<Track>,
+    /// Entries of a playlist file that aren't tracks, written back when it's saved
+    unresolved: Vec<Unresolved>,
+    /// Grouped from tags, so other albums can share the folder
+    grouped: bool,
+}
+
+/// Where a playlist came from
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistKind {
+    /// A folder or group of tags, tracks sorted by disc & number
+    #[default]
+    Album,
+    /// An M3U or PLS file, tracks in the order they're listed
+    File,
 }
 
 #[derive(Clone)]
@@ -63,17 +99,46 @@ pub struct Track {
     pub path: PathBuf,
     /// Track Metadata
     pub metadata: TrackMetadata,
+    /// Part of the file to play when a cue sheet splits it into tracks
+    pub cue: Option<CueSpan>,
 }
 
-#[derive(Clone)]
+/// Number, title & artists always have a value, falling back to the filename & playlist
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TrackMetadata {
     pub number: u32,
     pub title: String,
     pub artists: String,
-    pub year: u32,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub disc: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<u32>,
     pub total_duration: Duration,
-    pub bit_rate: u32,
-    pub sample_rate: u32,
+    pub bit_rate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    /// ReplayGain in dB relative to -18 LUFS
+    pub track_gain: Option<f32>,
+    pub album_gain: Option<f32>,
+    /// Peak sample as a linear value where 1.0 is full scale
+    pub track_peak: Option<f32>,
+    pub album_peak: Option<f32>,
+    /// What was missing when reading the file
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Something missing from a file that had to be guessed or left out
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    Unreadable,
+    NoTags,
+    Number,
+    Title,
+    Artist,
+    Year,
+    BitRate,
+    SampleRate,
 }
 
 /*
@@ -81,49 +146,112 @@ pub struct TrackMetadata {
  */
 
 impl SourceHandler {
-    pub fn build(path: PathBuf) -> Result<Self, Error> {
+    pub fn build(path: PathBuf, naming: NamingScheme, mode: LibraryMode) -> Result<Self, Error> {
+        let (source, errors) = Self::scan(path, naming, mode)?;
+        for e in errors {
+            eprintln!("{e}");
+        }
+
+        Ok(source)
+    }
+
+    /// Rescans the source directory in place, errors are dropped since the TUI is running
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let (source, _) = Self::scan(self.path.clone(), self.naming.clone(), self.mode)?;
+        *self = source;
+        Ok(())
+    }
+
+    /// Reads playlists & their tracks, returning any folders that were skipped as errors
+    fn scan(
+        path: PathBuf,
+        naming: NamingScheme,
+        mode: LibraryMode,
+    ) -> Result<(Self, Vec<Error>), Error> {
         let mut playlists = HashMap::new();
-        // Use indexes so tracks can be backtraced to playlist
-        let mut id = 0;
+        let mut errors = Vec::new();
+
+        // Make sure the source is readable before walking it
+        fs::read_dir(&path)?;
+        let mut index = LibraryIndex::load(&path);
+
+        match mode {
+            LibraryMode::Folders => {
+                let (found, rejected) = naming.find_playlists(&path);
+                for folder in rejected {
+                    errors.push(eyre!(
+                        "Skipped `{}`: {}",
+                        folder.path.display(),
+                        folder.reason
+                    ));
+                }
 
-        // Get items
-        let mut children: Vec<DirEntry> = fs::read_dir(&path)?
-            .filter_map(|child| child.ok())
-            .collect();
+                // Use indexes so tracks can be backtraced to playlist
+                for (id, folder) in found.into_iter().enumerate() {
+                    let mut playlist = Playlist::new(folder.title, folder.artists, folder.path, id);
+
+                    // Fill in tracks, only reading tags of files that changed since last run
+                    playlist.tracks = index.scan_playlist(&playlist);
+                    playlists.insert(id, playlist);
+                }
+            }
+            LibraryMode::Albums => {
+                for playlist in group_albums(&path, index.scan_tree(&path)) {
+                    playlists.insert(playlist.id, playlist);
+                }
+            }
+        }
 
-        // Try to sort and handle the items
-        children.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
-        children
-            .iter()
-            .filter_map(|child| {
-                if child.file_type().ok()?.is_dir() {
-                    // Is a directory
-                    let playlist =
-                        Playlist::build(child.file_name().into_string().unwrap(), child.path(), id);
-
-                    match playlist {
-                        Ok(v) => {
-                            id += 1;
-                            Some(v)
-                        }
-                        Err(v) => {
-                            eprintln!("{v}");
-                            None
-                        }
+        // Playlist files come after albums
+        for file in playlist_file::find(&path) {
+            let entries = match playlist_file::read(&file) {
+                Ok(v) => v,
+                Err(e) => {
+                    errors.push(e.wrap_err(format!("Failed to read `{}`", file.display())));
+                    continue;
+                }
+            };
+
+            let id = playlists.len();
+            let mut playlist = Playlist::from_file(file, id);
+            let mut tracks: Vec<Track> = Vec::new();
+            for entry in entries {
+                // Missing files & streams are left out, but kept in the file
+                let mut track = match index.scan_file(entry.path, &playlist) {
+                    Some(v) => v,
+                    None => {
+                        playlist.unresolved.push(Unresolved {
+                            after: tracks.last().map(|track| track.key()),
+                            raw: entry.raw,
+                        });
+                        continue;
                     }
-                } else {
-                    // Is regular file
-                    None
+                };
+                if let Some(title) = entry.title {
+                    apply_entry_title(&mut track.metadata, &title);
                 }
-            })
-            .for_each(|child| {
-                playlists.insert(child.id, child);
-            });
 
-        Ok(Self {
-            path: path.clone(),
-            playlists: playlists,
-        })
+                tracks.push(track);
+            }
+
+            playlist.tracks = tracks;
+            playlists.insert(id, playlist);
+        }
+
+        index.prune();
+        if let Err(e) = index.save() {
+            errors.push(e.wrap_err("Failed to save library index"));
+        }
+
+        Ok((
+            Self {
+                path: path.clone(),
+                playlists: playlists,
+                naming,
+                mode,
+            },
+            errors,
+        ))
     }
 
     /// Lists out playlists to be displayed
@@ -137,7 +265,13 @@ impl SourceHandler {
         // generate list based on sort
         for playlist in sorted {
             let title = Line::styled(&playlist.title, Style::new().bold());
-            let artists = Line::styled(&playlist.artists, Style::new().dim().italic());
+            let artists = match playlist.kind {
+                PlaylistKind::Album => Line::styled(&playlist.artists, Style::new().dim().italic()),
+                PlaylistKind::File => Line::styled(
+                    format!("Playlist, {} tracks", playlist.tracks.len()),
+                    Style::new().cyan().dim().italic(),
+                ),
+            };
 
             result.push(Text::from(vec![title, artists]));
         }
@@ -145,72 +279,150 @@ impl SourceHandler {
         return result;
     }
 
+    /// Finds a playlist by its [`Playlist::key`]
+    pub fn find_playlist(&self, key: &Path) -> Option<&Playlist> {
+        self.playlists
+            .values()
+            .find(|playlist| playlist.key() == key)
+    }
+
+    /// Finds a track by its [`Track::key`]
+    pub fn find_track(&self, key: &Path) -> Option<Track> {
+        self.playlists
+            .values()
+            .filter(|playlist| key.starts_with(&playlist.path))
+            .find_map(|playlist| playlist.iter().find(|track| track.has_key(key)))
+            .cloned()
+    }
+
+    /// Tracks that had missing tags, in playlist order
+    pub fn diagnostics(&self) -> Vec<&Track> {
+        let mut sorted: Vec<&Playlist> = self.playlists.values().collect();
+        sorted.sort_by_key(|playlist| playlist.id);
+
+        sorted
+            .into_iter()
+            .flat_map(|playlist| playlist.tracks.iter())
+            .filter(|track| !track.metadata.diagnostics.is_empty())
+            .collect()
+    }
+
+    /// Lists out tracks with missing tags & what was missing
+    pub fn display_diagnostics(&self) -> Vec<Text<'_>> {
+        let mut result = Vec::new();
+
+        for track in self.diagnostics() {
+            let path = track.path.strip_prefix(&self.path).unwrap_or(&track.path);
+            let missing: Vec<&str> = track
+                .metadata
+                .diagnostics
+                .iter()
+                .map(|v| v.describe())
+                .collect();
+
+            let path = Line::styled(path.display().to_string(), Style::new().bold());
+            let missing = Line::styled(missing.join(", "), Style::new().dim().italic());
+            result.push(Text::from(vec![path, missing]));
+        }
+
+        result
+    }
+
+    /// Playlists backed by M3U or PLS files, in list order
+    pub fn user_playlists(&self) -> Vec<&Playlist> {
+        let mut sorted: Vec<&Playlist> = self
+            .playlists
+            .values()
+            .filter(|playlist| playlist.kind == PlaylistKind::File)
+            .collect();
+        sorted.sort_by_key(|playlist| playlist.id);
+        sorted
+    }
+
+    /// Lists out user playlists to pick from
+    pub fn display_user_playlists(&self) -> Vec<Text<'_>> {
+        self.user_playlists()
+            .into_iter()
+            .map(|playlist| {
+                Text::from(vec![
+                    Line::styled(&playlist.title, Style::new().bold()),
+                    Line::styled(
+                        format!("{} tracks", playlist.tracks.len()),
+                        Style::new().dim().italic(),
+                    ),
+                ])
+            })
+            .collect()
+    }
+
     /// Number of tracks in a playlist at index
     pub fn num_tracks_in_playlists(&self, id: usize) -> usize {
         match self.playlists.get(&id) {
-            Some(v) => v.tracks().len(),
+            Some(v) => v.tracks.len(),
             None => 0,
         }
     }
 }
 
 impl Playlist {
-    pub fn build(name: String, path: PathBuf, id: usize) -> Result<Self, Error> {
-        // Title & Artist(s)
-        let mut name_parts = name.split(" - ");
-        let artists = name_parts.next().unwrap().trim().to_string();
-        let title = match name_parts.next() {
-            Some(v) => v.trim().to_string(),
-            None => {
-                bail!(
-                    "Unexpected name format. Desired: [INDEX] [ARTIST] - [TITLE]\nNo Artist / Index found",
-                );
-            }
-        };
-
-        Ok(Self {
+    pub fn new(title: String, artists: String, path: PathBuf, id: usize) -> Self {
+        Self {
             title,
             artists,
             path,
             id,
-        })
+            kind: PlaylistKind::Album,
+            tracks: Vec::new(),
+            unresolved: Vec::new(),
+            grouped: false,
+        }
     }
 
-    /// fetches tracks
-    pub fn tracks<'a>(&self) -> Vec<Track> {
-        // If we get a cache hit, return it
-        let mut cache_lock = TRACK_CACHE.lock().unwrap();
-        if let Some(tracks) = cache_lock.get(&self.id) {
-            return tracks.clone();
-        }
+    /// Empty playlist named after an M3U or PLS file
+    pub fn from_file(path: PathBuf, id: usize) -> Self {
+        let title = path
+            .file_stem()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
 
-        // Fetch tracks if no cache hit
-        let children: Vec<Track> = fs::read_dir(&self.path)
-            .expect("Failed to read playlist")
-            .filter_map(|child| child.ok()) // Is able to read
-            .filter_map(|child| {
-                if child.file_type().ok()?.is_file() {
-                    // Is a file
-                    Track::try_new(child.path(), self.id)
-                } else {
-                    // Is dir
-                    None
-                }
-            })
-            .collect();
+        Self {
+            title,
+            artists: String::new(),
+            path,
+            id,
+            kind: PlaylistKind::File,
+            tracks: Vec::new(),
+            unresolved: Vec::new(),
+            grouped: false,
+        }
+    }
 
-        // Save to cache
-        cache_lock.put(self.id, children.clone());
+    /// Path that tells playlists apart, albums grouped from tags get `#Artist - Title` added
+    pub fn key(&self) -> PathBuf {
+        match self.grouped {
+            true => {
+                let mut key = self.path.clone().into_os_string();
+                key.push(format!("#{} - {}", self.artists, self.title));
+                PathBuf::from(key)
+            }
+            false => self.path.clone(),
+        }
+    }
 
-        return children;
+    /// fetches tracks
+    pub fn tracks(&self) -> Vec<Track> {
+        self.tracks.clone()
     }
 
-    /// Gets specific track based off number
-    pub fn get(&self, number: u32) -> Option<Track> {
-        let tracks = self.tracks();
+    /// Same as [`Playlist::tracks`] without cloning them
+    pub fn iter(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter()
+    }
 
-        for i in tracks {
-            if i.metadata.number == number {
+    /// Gets specific track based off disc & number
+    pub fn get(&self, disc: u32, number: u32) -> Option<Track> {
+        for i in &self.tracks {
+            if i.metadata.disc() == disc && i.metadata.number == number {
                 return Some(i.clone());
             }
         }
@@ -218,50 +430,487 @@ impl Playlist {
         return None;
     }
 
+    /// Gets the track at a position in play order
+    pub fn track_at(&self, index: usize) -> Option<Track> {
+        self.tracks.get(index).cloned()
+    }
+
+    /// Position of a track in play order
+    pub fn position(&self, track: &Track) -> Option<usize> {
+        self.tracks.iter().position(|v| v.key() == track.key())
+    }
+
+    /// Whether tracks span more than one disc
+    pub fn is_multi_disc(&self) -> bool {
+        if self.kind == PlaylistKind::File {
+            return false;
+        }
+
+        match self.tracks.first() {
+            Some(first) => self
+                .tracks
+                .iter()
+                .any(|v| v.metadata.disc() != first.metadata.disc()),
+            None => false,
+        }
+    }
+
     /// Lists out tracks in a playlist to be displayed
+    ///
+    /// The first track of each disc carries the disc header so items still line up with tracks
     pub fn display(&self) -> Vec<Text> {
         let mut result = Vec::new();
+        let multi_disc = self.is_multi_disc();
+        let mut last_disc = None;
+
+        // Format the names, marking tracks that had missing tags
+        for (position, i) in self.tracks.iter().enumerate() {
+            let mut lines = Vec::new();
+            if multi_disc && last_disc != Some(i.metadata.disc()) {
+                let header = match i.metadata.disc_total {
+                    Some(total) => format!("Disc {}/{}", i.metadata.disc(), total),
+                    None => format!("Disc {}", i.metadata.disc()),
+                };
+                lines.push(Line::styled(header, Style::new().green().bold()));
+                last_disc = Some(i.metadata.disc());
+            }
+
+            // Playlist files are numbered by position
+            let number = match self.kind {
+                PlaylistKind::Album => i.metadata.number,
+                PlaylistKind::File => position as u32 + 1,
+            };
+            let mut line = Line::from(format!("{:2} {}", number, i.metadata.title));
+            if !i.metadata.diagnostics.is_empty() {
+                line.push_span(Span::styled(" !", Style::new().yellow().bold()));
+            }
+            lines.push(line);
 
-        // Format the names
-        for i in self.tracks() {
-            result.push(Text::from(format!(
-                "{:2} {}",
-                i.metadata.number, i.metadata.title,
-            )));
+            result.push(Text::from(lines));
         }
 
         return result;
     }
+
+    /*
+     * Editing, only for playlist files
+     */
+
+    /// Adds tracks to the end, pointing them at this playlist
+    pub fn append(&mut self, tracks: Vec<Track>) {
+        for mut track in tracks {
+            track.playlist_index = self.id;
+            self.tracks.push(track);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Track> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+
+        Some(self.tracks.remove(index))
+    }
+
+    /// Moves a track one place earlier, returns its new index
+    pub fn move_up(&mut self, index: usize) -> usize {
+        if index == 0 || index >= self.tracks.len() {
+            return index;
+        }
+
+        self.tracks.swap(index, index - 1);
+        index - 1
+    }
+
+    /// Moves a track one place later, returns its new index
+    pub fn move_down(&mut self, index: usize) -> usize {
+        if index + 1 >= self.tracks.len() {
+            return index;
+        }
+
+        self.tracks.swap(index, index + 1);
+        index + 1
+    }
+
+    /// Writes tracks back to the playlist file, returns how many had to be left out
+    pub fn save(&self) -> Result<usize, Error> {
+        playlist_file::save(&self.path, &self.tracks, &self.unresolved)
+    }
 }
 
 impl Track {
-    /// Returns Some if codec is supported, otherwise returns none
-    pub fn try_new(path: PathBuf, playlist_index: usize) -> Option<Self> {
-        let extension = path.extension()?.to_str()?;
-        if AUDIO_EXTENSIONS.contains(&extension) {
-            Some(Self {
-                path: path.clone(),
-                playlist_index,
-                metadata: read_track_metadata(path),
-            })
-        } else {
-            None
+    /// Points the track at no playlist, once its playlist is gone from the library
+    pub fn orphan(&mut self) {
+        self.playlist_index = usize::MAX;
+    }
+
+    /// Track for one cue sheet entry, the cue's titles & performers win over the file's tags
+    pub fn from_cue(file: Track, sheet: &CueSheet, cue_track: &CueTrack) -> Self {
+        let span = cue_track.span;
+        let mut metadata = file.metadata;
+
+        metadata.number = span.index;
+        metadata.title = match &cue_track.title {
+            Some(v) => v.clone(),
+            None => format!("Track {:02}", span.index),
+        };
+        if let Some(performer) = cue_track.performer.as_ref().or(sheet.performer.as_ref()) {
+            metadata.artists = performer.clone();
+        }
+        if let Some(title) = &sheet.title {
+            metadata.album = Some(title.clone());
+        }
+        if let Some(performer) = &sheet.performer {
+            metadata.album_artist = Some(performer.clone());
+        }
+        metadata.total_duration = match span.end {
+            Some(end) => end.saturating_sub(span.start),
+            None => metadata.total_duration.saturating_sub(span.start),
+        };
+
+        // The file's gain covers the whole album
+        metadata.album_gain = metadata.album_gain.or(metadata.track_gain.take());
+        metadata.album_peak = metadata.album_peak.or(metadata.track_peak.take());
+
+        // Whatever the cue filled in isn't missing anymore
+        metadata.diagnostics.retain(|v| match v {
+            Diagnostic::Number => false,
+            Diagnostic::Title => cue_track.title.is_none(),
+            Diagnostic::Artist => cue_track.performer.is_none() && sheet.performer.is_none(),
+            _ => true,
+        });
+
+        Self {
+            playlist_index: file.playlist_index,
+            path: file.path,
+            metadata,
+            cue: Some(span),
+        }
+    }
+
+    /// Identifies a track, cue tracks share a file so get `#number` appended
+    pub fn key(&self) -> PathBuf {
+        match &self.cue {
+            Some(cue) => {
+                let mut key = self.path.clone().into_os_string();
+                key.push(format!("#{}", cue.index));
+                PathBuf::from(key)
+            }
+            None => self.path.clone(),
         }
     }
+
+    /// Same as comparing [`Track::key`], without building it for whole files
+    pub fn has_key(&self, key: &Path) -> bool {
+        match &self.cue {
+            Some(_) => self.key() == key,
+            None => self.path == key,
+        }
+    }
+}
+
+impl TrackMetadata {
+    /// Disc number, untagged tracks are on the first disc
+    pub fn disc(&self) -> u32 {
+        self.disc.unwrap_or(1)
+    }
+
+    /// Uses the playlist's artists when the tags had no artist at all
+    ///
+    /// Kept out of [`read_track_metadata`] since a file can be in more than one playlist
+    pub fn fill_artists(&mut self, playlist_artists: &str) {
+        if self.artists.is_empty() && self.diagnostics.contains(&Diagnostic::Artist) {
+            self.artists = playlist_artists.to_string();
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Diagnostic::Unreadable => "unreadable file",
+            Diagnostic::NoTags => "no tags",
+            Diagnostic::Number => "no track number",
+            Diagnostic::Title => "no title",
+            Diagnostic::Artist => "no artist",
+            Diagnostic::Year => "no year",
+            Diagnostic::BitRate => "unknown bit rate",
+            Diagnostic::SampleRate => "unknown sample rate",
+        }
+    }
+}
+
+/// Groups tracks into albums by album artist & album tags, sorted by artist then album
+///
+/// Tracks without an album tag are grouped by the folder they're in
+fn group_albums(source: &Path, tracks: Vec<Track>) -> Vec<Playlist> {
+    let mut albums: HashMap<(String, String), Vec<Track>> = HashMap::new();
+
+    for track in tracks {
+        let artists = match &track.metadata.album_artist {
+            Some(v) => v.clone(),
+            None => track.metadata.artists.clone(),
+        };
+        let title = match &track.metadata.album {
+            Some(v) => v.clone(),
+            None => track
+                .path
+                .parent()
+                .and_then(|v| v.file_name())
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+
+        albums.entry((artists, title)).or_default().push(track);
+    }
+
+    let mut sorted: Vec<((String, String), Vec<Track>)> = albums.into_iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+    sorted
+        .into_iter()
+        .enumerate()
+        .map(|(id, ((artists, title), mut tracks))| {
+            // Deepest folder holding every track, used for cover art
+            let mut path = tracks[0].path.parent().unwrap_or(source).to_path_buf();
+            while !tracks.iter().all(|v| v.path.starts_with(&path)) {
+                if !path.pop() {
+                    break;
+                }
+            }
+
+            for track in tracks.iter_mut() {
+                track.playlist_index = id;
+            }
+            sort_tracks(&mut tracks);
+
+            Playlist {
+                id,
+                title,
+                artists,
+                path,
+                kind: PlaylistKind::Album,
+                tracks,
+                unresolved: Vec::new(),
+                grouped: true,
+            }
+        })
+        .collect()
+}
+
+/// Whether the file has a supported audio extension
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|v| v.to_str())
+        .is_some_and(|v| AUDIO_EXTENSIONS.contains(&v))
+}
+
+/// Orders tracks by disc then number
+///
+/// Tracks without any number go after the numbered ones in filename order
+pub fn sort_tracks(tracks: &mut [Track]) {
+    tracks.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut next_number = tracks.iter().map(|v| v.metadata.number).max().unwrap_or(0);
+    for track in tracks.iter_mut().filter(|v| v.metadata.number == 0) {
+        next_number += 1;
+        track.metadata.number = next_number;
+    }
+
+    tracks.sort_by_key(|v| (v.metadata.disc(), v.metadata.number));
+}
+
+/// Cover image file in a folder, if there is one
+pub fn cover_file(folder: &Path) -> Option<PathBuf> {
+    COVER_FILES
+        .iter()
+        .map(|name| folder.join(name))
+        .find(|path| path.is_file())
 }
 
-fn read_track_metadata(path: PathBuf) -> TrackMetadata {
-    let tagged_file = lofty::read_from_path(path).unwrap();
-    let tag = tagged_file.primary_tag().unwrap();
-    let properties = tagged_file.properties();
+/// Raw bytes of the picture embedded in a file, preferring the front cover
+pub fn embedded_cover(path: &Path) -> Option<Vec<u8>> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let pictures = tagged_file.primary_tag()?.pictures();
+
+    // Any picture is better than nothing
+    let picture = pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or(pictures.first())?;
+
+    Some(picture.data().to_vec())
+}
+
+/// Reads tags, guessing from the filename instead of failing on missing ones
+pub fn read_track_metadata(path: &Path) -> TrackMetadata {
+    let mut diagnostics = Vec::new();
+
+    let tagged_file = match lofty::read_from_path(path) {
+        Ok(v) => Some(v),
+        Err(_) => {
+            diagnostics.push(Diagnostic::Unreadable);
+            None
+        }
+    };
+    let tag = tagged_file
+        .as_ref()
+        .and_then(|v| v.primary_tag().or(v.first_tag()));
+    if tagged_file.is_some() && tag.is_none() {
+        diagnostics.push(Diagnostic::NoTags);
+    }
+    let properties = tagged_file.as_ref().map(|v| v.properties());
+
+    // Fall back to `01 - Name.flac` style filenames
+    let number = match tag.and_then(|v| v.track()) {
+        Some(v) => v,
+        None => {
+            diagnostics.push(Diagnostic::Number);
+            number_from_filename(path).unwrap_or(0)
+        }
+    };
+    let title = match tag.and_then(|v| v.title()) {
+        Some(v) => v.to_string(),
+        None => {
+            diagnostics.push(Diagnostic::Title);
+            title_from_filename(path)
+        }
+    };
+    let album_artist = tag
+        .and_then(|v| v.get_string(&ItemKey::AlbumArtist))
+        .map(|v| v.to_string());
+    let artists = match tag.and_then(|v| v.artist()) {
+        Some(v) => v.to_string(),
+        None => {
+            diagnostics.push(Diagnostic::Artist);
+            album_artist.clone().unwrap_or_default()
+        }
+    };
+
+    // Nothing sensible to guess
+    let year = tag.and_then(|v| v.year());
+    if year.is_none() {
+        diagnostics.push(Diagnostic::Year);
+    }
+    let bit_rate = properties.and_then(|v| v.overall_bitrate());
+    if bit_rate.is_none() {
+        diagnostics.push(Diagnostic::BitRate);
+    }
+    let sample_rate = properties.and_then(|v| v.sample_rate());
+    if sample_rate.is_none() {
+        diagnostics.push(Diagnostic::SampleRate);
+    }
 
     TrackMetadata {
-        number: tag.track().unwrap(),
-        title: tag.title().unwrap().to_string(),
-        artists: tag.artist().unwrap().to_string(),
-        year: tag.year().unwrap(),
-        total_duration: properties.duration(),
-        bit_rate: properties.overall_bitrate().unwrap(),
-        sample_rate: properties.sample_rate().unwrap(),
+        number,
+        title,
+        artists,
+        album: tag.and_then(|v| v.album()).map(|v| v.to_string()),
+        album_artist,
+        disc: tag.and_then(|v| v.disk()),
+        disc_total: tag.and_then(|v| v.disk_total()),
+        year,
+        total_duration: properties.map(|v| v.duration()).unwrap_or_default(),
+        bit_rate,
+        sample_rate,
+        track_gain: tag.and_then(|v| read_gain(v, ItemKey::ReplayGainTrackGain, "R128_TRACK_GAIN")),
+        album_gain: tag.and_then(|v| read_gain(v, ItemKey::ReplayGainAlbumGain, "R128_ALBUM_GAIN")),
+        track_peak: tag.and_then(|v| read_number(v, ItemKey::ReplayGainTrackPeak)),
+        album_peak: tag.and_then(|v| read_number(v, ItemKey::ReplayGainAlbumPeak)),
+        diagnostics,
+    }
+}
+
+/// Fills in a missing title & artist from a playlist's `Artist - Title`
+fn apply_entry_title(metadata: &mut TrackMetadata, entry_title: &str) {
+    let (artists, title) = match entry_title.split_once(" - ") {
+        Some((artists, title)) => (Some(artists.trim()), title.trim()),
+        None => (None, entry_title.trim()),
+    };
+
+    if metadata.diagnostics.contains(&Diagnostic::Title) {
+        metadata.title = title.to_string();
+    }
+    if let Some(artists) = artists
+        && metadata.diagnostics.contains(&Diagnostic::Artist)
+    {
+        metadata.artists = artists.to_string();
+    }
+}
+
+/// Leading number of a filename like `01 - Name.flac`
+fn number_from_filename(path: &Path) -> Option<u32> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits: String = stem.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// Filename without the extension or leading track number
+fn title_from_filename(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let title = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '-' || c == '.' || c == '_')
+        .trim();
+
+    // Filename was only a number
+    match title.is_empty() {
+        true => stem,
+        false => title.to_string(),
+    }
+}
+
+/// Reads a ReplayGain tag, falling back to the R128 tag Opus files use
+fn read_gain(tag: &Tag, key: ItemKey, r128_key: &str) -> Option<f32> {
+    if let Some(gain) = read_number(tag, key) {
+        return Some(gain);
+    }
+
+    // R128 is a Q7.8 number relative to -23 LUFS, ReplayGain uses -18 LUFS
+    let r128 = tag.get_string(&ItemKey::Unknown(r128_key.to_string()))?;
+    let r128: i16 = r128.trim().parse().ok()?;
+    Some(r128 as f32 / 256.0 + 5.0)
+}
+
+/// Parses tags like `-6.54 dB` or `0.988547`
+fn read_number(tag: &Tag, key: ItemKey) -> Option<f32> {
+    let value = tag.get_string(&key)?;
+    value
+        .trim()
+        .trim_end_matches("dB")
+        .trim_end_matches("db")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Track with made up tags, for tests that don't read files
+#[cfg(test)]
+pub fn test_track(path: PathBuf, title: &str) -> Track {
+    Track {
+        playlist_index: 0,
+        path,
+        metadata: TrackMetadata {
+            number: 1,
+            title: title.to_string(),
+            artists: "Artist".to_string(),
+            album: None,
+            album_artist: None,
+            disc: None,
+            disc_total: None,
+            year: None,
+            total_duration: Duration::from_secs(60),
+            bit_rate: None,
+            sample_rate: None,
+            track_gain: None,
+            album_gain: None,
+            track_peak: None,
+            album_peak: None,
+            diagnostics: Vec::new(),
+        },
+        cue: None,
     }
 }
@@ -0,0 +1,472 @@
+/*
+ * Handles the HTTP API remotes on the network control the player through
+ *
+ * JSON in & out, `GET /events` streams status & queue changes as Server-Sent Events
+ */
+
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{Error, eyre};
+use serde::Serialize;
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    audio::AudioHandler,
+    cli::{self, AlbumEntry, Target, TrackEntry},
+    config::Config,
+    control::{self, Command, Reply, Status},
+    event::{AppEvent, Event},
+    files::{self, SourceHandler},
+};
+
+/*
+ * Statics
+ */
+
+/// Comment sent to idle event streams so dropped clients are noticed
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Largest request body read, commands are a few fields at most
+const MAX_BODY: u64 = 64 * 1024;
+
+/*
+ * Data
+ */
+
+/// Running server, kept up to date by [`Http::update`]
+pub struct Http {
+    /// Where it ended up listening, the port is picked when given 0
+    address: Option<SocketAddr>,
+    shared: Arc<Shared>,
+    /// Keys of the queued tracks, to notice changes cheaply
+    queue_keys: Vec<PathBuf>,
+}
+
+/// Snapshot the request threads answer from
+#[derive(Default)]
+struct Shared {
+    status: Mutex<Status>,
+    queue: Mutex<Vec<TrackEntry>>,
+    library: Mutex<Library>,
+    /// Current track's file & playlist folder
+    cover: Mutex<Option<(PathBuf, PathBuf)>>,
+    /// Open event streams, dropped once they disconnect
+    clients: Mutex<Vec<mpsc::Sender<String>>>,
+}
+
+/// Albums with their tracks at the same index
+#[derive(Default)]
+struct Library {
+    albums: Vec<AlbumEntry>,
+    tracks: Vec<Vec<TrackEntry>>,
+}
+
+/*
+ * Functions
+ */
+
+impl Http {
+    /// Binds the address & spawns a thread answering requests, commands are sent as [`Event::App`]
+    pub fn spawn(address: &str, sender: mpsc::Sender<Event>) -> Result<Self, Error> {
+        let server = Server::http(address).map_err(|e| eyre!("Failed to bind `{address}`: {e}"))?;
+        let shared = Arc::new(Shared::default());
+        let bound = server.server_addr().to_ip();
+
+        let state = shared.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                // Event streams never finish, don't block others
+                let sender = sender.clone();
+                let state = state.clone();
+                thread::spawn(move || handle_request(request, &sender, &state));
+            }
+        });
+
+        Ok(Self {
+            address: bound,
+            shared,
+            queue_keys: Vec::new(),
+        })
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+
+    /// Refreshes the snapshot, streaming whatever changed
+    pub fn update(&mut self, audio: &AudioHandler, config: &Config, source: &SourceHandler) {
+        let status = Status::read(audio, config);
+        let changed = {
+            let mut previous = self.shared.status.lock().unwrap();
+            // Position only counts once a second, otherwise every tick would be sent
+            let changed = Status {
+                position: previous.position.trunc(),
+                ..previous.clone()
+            } != Status {
+                position: status.position.trunc(),
+                ..status.clone()
+            };
+            *previous = status.clone();
+            changed
+        };
+        if changed {
+            self.broadcast("status", &status);
+        }
+
+        let queue_keys: Vec<PathBuf> = audio.queue.iter().map(|track| track.key()).collect();
+        if queue_keys != self.queue_keys {
+            let queue: Vec<TrackEntry> = audio
+                .queue
+                .iter()
+                .map(|track| TrackEntry::new(source, track))
+                .collect();
+            self.broadcast("queue", &queue);
+            *self.shared.queue.lock().unwrap() = queue;
+            self.queue_keys = queue_keys;
+        }
+
+        *self.shared.cover.lock().unwrap() = audio.current_track.as_ref().and_then(|current| {
+            let playlist = source.playlists.get(&current.track.playlist_index)?;
+            Some((current.track.path.clone(), playlist.path.clone()))
+        });
+    }
+
+    /// Rebuilds what the library routes answer with, after a rescan
+    pub fn set_library(&self, source: &SourceHandler) {
+        let albums = cli::albums(source);
+        let tracks = albums
+            .iter()
+            .map(|album| match source.playlists.get(&album.id) {
+                Some(playlist) => cli::tracks(source, Some(&Target::Playlist(playlist))),
+                None => Vec::new(),
+            })
+            .collect();
+
+        *self.shared.library.lock().unwrap() = Library { albums, tracks };
+    }
+
+    /// Sends an event to every open stream, forgetting the closed ones
+    fn broadcast<T: Serialize>(&self, name: &str, data: &T) {
+        let message = format!(
+            "event: {name}\ndata: {}\n\n",
+            serde_json::to_string(data).unwrap()
+        );
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(message.clone()).is_ok());
+    }
+}
+
+/// Routes a request, everything but the cover & event stream answers in JSON
+fn handle_request(mut request: Request, sender: &mpsc::Sender<Event>, state: &Shared) {
+    let url = request.url().to_string();
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    // Reachable from the network, don't read whatever is sent
+    let mut body = String::new();
+    let _ = request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body);
+    if body.len() as u64 > MAX_BODY {
+        let reply = json(&Reply::error("Body is too large".to_string()));
+        let _ = request.respond(
+            Response::from_string(reply)
+                .with_status_code(413)
+                .with_header(header("Content-Type", "application/json")),
+        );
+        return;
+    }
+
+    // Borrowing it would keep the request from being moved into a response
+    let method = request.method().clone();
+    let (code, reply) = match (method, path.as_slice()) {
+        (Method::Get, ["events"]) => return stream_events(request, state),
+        (Method::Get, ["cover"]) => return respond_cover(request, state),
+
+        (Method::Get, ["status"]) => (200, json(&*state.status.lock().unwrap())),
+        (Method::Get, ["queue"]) => (200, json(&*state.queue.lock().unwrap())),
+        (Method::Get, ["albums"]) => (200, json(&state.library.lock().unwrap().albums)),
+        (Method::Get, ["albums", index]) => {
+            let library = state.library.lock().unwrap();
+            match index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| library.tracks.get(i))
+            {
+                Some(tracks) => (200, json(tracks)),
+                None => not_found(),
+            }
+        }
+
+        // Queue
+        (Method::Post, ["queue"]) => command("enqueue", &body, sender, state),
+        (Method::Delete, ["queue"]) => send(AppEvent::QueueClearAll, sender),
+        (Method::Delete, ["queue", index]) => remove_queued(index, sender, state),
+
+        // Transport
+        (Method::Post, [name @ ("play" | "pause" | "toggle" | "next" | "previous")]) => {
+            command(name, "", sender, state)
+        }
+        (Method::Post, [name @ ("seek" | "volume")]) => command(name, &body, sender, state),
+
+        _ => not_found(),
+    };
+
+    let _ = request.respond(
+        Response::from_string(reply)
+            .with_status_code(code)
+            .with_header(header("Content-Type", "application/json")),
+    );
+}
+
+/// Runs a control command, its fields taken from the JSON body
+fn command(name: &str, body: &str, sender: &mpsc::Sender<Event>, state: &Shared) -> (u16, String) {
+    let mut fields = match body.trim().is_empty() {
+        true => Value::Object(Default::default()),
+        false => match serde_json::from_str::<Value>(body) {
+            Ok(v) if v.is_object() => v,
+            _ => {
+                return (
+                    400,
+                    json(&Reply::error("Body has to be a JSON object".to_string())),
+                );
+            }
+        },
+    };
+    fields["command"] = Value::from(name);
+
+    let reply = match serde_json::from_value::<Command>(fields) {
+        Ok(command) => control::forward(command, sender, &state.status),
+        Err(e) => Reply::error(format!("Invalid command: {e}")),
+    };
+    match reply.ok {
+        true => (200, json(&reply)),
+        false => (400, json(&reply)),
+    }
+}
+
+fn send(event: AppEvent, sender: &mpsc::Sender<Event>) -> (u16, String) {
+    match sender.send(Event::App(event)) {
+        Ok(_) => (200, json(&Reply::ok())),
+        Err(_) => (
+            503,
+            json(&Reply::error("Player is shutting down".to_string())),
+        ),
+    }
+}
+
+/// Removes a queued track by its index in `GET /queue`, as long as the queue hasn't changed since
+fn remove_queued(index: &str, sender: &mpsc::Sender<Event>, state: &Shared) -> (u16, String) {
+    let key = match index.parse::<usize>() {
+        Ok(index) => state
+            .queue
+            .lock()
+            .unwrap()
+            .get(index)
+            .map(|track| (index, track.path.clone())),
+        Err(_) => None,
+    };
+    let (index, key) = match key {
+        Some(v) => v,
+        None => return not_found(),
+    };
+
+    match control::ask(sender, |removed| {
+        AppEvent::QueueRemoveAt(index, key, removed)
+    }) {
+        Ok(true) => (200, json(&Reply::ok())),
+        Ok(false) => (
+            409,
+            json(&Reply::error("Queue changed, fetch it again".to_string())),
+        ),
+        Err(e) => (503, json(&Reply::error(e))),
+    }
+}
+
+/// Cover of the current track as found on disk, embedded first
+fn respond_cover(request: Request, state: &Shared) {
+    let cover = state.cover.lock().unwrap().clone();
+    let data = cover.and_then(|(track, playlist)| {
+        files::embedded_cover(&track).or_else(|| std::fs::read(files::cover_file(&playlist)?).ok())
+    });
+
+    let _ = match data {
+        Some(data) => {
+            let mime = image::guess_format(&data)
+                .map(|format| format.to_mime_type())
+                .unwrap_or("application/octet-stream");
+            request.respond(Response::from_data(data).with_header(header("Content-Type", mime)))
+        }
+        None => {
+            let (code, reply) = not_found();
+            request.respond(
+                Response::from_string(reply)
+                    .with_status_code(code)
+                    .with_header(header("Content-Type", "application/json")),
+            )
+        }
+    };
+}
+
+/// Keeps the connection open, writing events as they come until either side closes it
+fn stream_events(request: Request, state: &Shared) {
+    let (client, receiver) = mpsc::channel();
+
+    // Start with where things are, then changes
+    let initial = format!(
+        "event: status\ndata: {}\n\nevent: queue\ndata: {}\n\n",
+        json(&*state.status.lock().unwrap()),
+        json(&*state.queue.lock().unwrap())
+    );
+    let _ = client.send(initial);
+    state.clients.lock().unwrap().push(client);
+
+    // Written raw, responses would buffer the stream
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if writer.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+
+    loop {
+        let message = match receiver.recv_timeout(KEEP_ALIVE) {
+            Ok(v) => v,
+            Err(mpsc::RecvTimeoutError::Timeout) => ":\n\n".to_string(),
+            // The player is gone
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        if writer.write_all(message.as_bytes()).is_err() || writer.flush().is_err() {
+            return;
+        }
+    }
+}
+
+fn not_found() -> (u16, String) {
+    (404, json(&Reply::error("Not found".to_string())))
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn header(name: &str, value: &str) -> Header {
+    // Only given constants & mime types, which are valid
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpStream};
+
+    use super::*;
+
+    /// Sends a request & reads the whole response, the server closes after answering
+    fn request(http: &Http, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(http.address().unwrap()).unwrap();
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let code = response
+            .split(' ')
+            .nth(1)
+            .and_then(|v| v.parse().ok())
+            .unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (code, body)
+    }
+
+    #[test]
+    fn requests_become_events() {
+        let (sender, receiver) = mpsc::channel();
+        let http = Http::spawn("127.0.0.1:0", sender).unwrap();
+
+        let (code, body) = request(&http, "GET", "/status", "");
+        assert_eq!(code, 200);
+        let status: Status = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, Status::default());
+
+        let (code, _) = request(&http, "POST", "/next", "");
+        assert_eq!(code, 200);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::App(AppEvent::PlayNext))
+        ));
+
+        let (code, _) = request(&http, "POST", "/volume", r#"{"level":0.5}"#);
+        assert_eq!(code, 200);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::App(AppEvent::VolumeSet(level))) if level == 0.5
+        ));
+
+        // Refused before reaching the player
+        let (code, _) = request(&http, "POST", "/volume", r#"{"level":2}"#);
+        assert_eq!(code, 400);
+        let (code, _) = request(&http, "GET", "/albums/0", "");
+        assert_eq!(code, 404);
+        let (code, _) = request(&http, "DELETE", "/queue/0", "");
+        assert_eq!(code, 404);
+        let body = format!(
+            r#"{{"level":0.5,"pad":"{}"}}"#,
+            "a".repeat(MAX_BODY as usize)
+        );
+        let (code, _) = request(&http, "POST", "/volume", &body);
+        assert_eq!(code, 413);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn removals_check_the_queue_hasnt_moved() {
+        let (sender, receiver) = mpsc::channel();
+        let http = Http::spawn("127.0.0.1:0", sender).unwrap();
+        *http.shared.queue.lock().unwrap() = vec![TrackEntry {
+            album: "Album".to_string(),
+            disc: 1,
+            number: 1,
+            title: "Title".to_string(),
+            artists: "Artist".to_string(),
+            duration: 60.0,
+            path: PathBuf::from("/music/Album/01.flac"),
+        }];
+
+        // Stands in for the player, whose queue has moved on
+        let player = thread::spawn(move || match receiver.recv() {
+            Ok(Event::App(AppEvent::QueueRemoveAt(index, key, removed))) => {
+                removed.send(false).unwrap();
+                (index, key)
+            }
+            _ => panic!("Expected a removal"),
+        });
+
+        let (code, _) = request(&http, "DELETE", "/queue/0", "");
+        assert_eq!(code, 409);
+        assert_eq!(
+            player.join().unwrap(),
+            (0, PathBuf::from("/music/Album/01.flac"))
+        );
+    }
+}
@@ -0,0 +1,476 @@
+/*
+ * Handles rebindable keys
+ *
+ * Bindings map keys to `AppEvent` names, a key can be a chord like `ctrl+up` or a sequence like `gg`
+ * or `g t`. Several keys can trigger the same event, e.g. vim style lists:
+ * `"j": "ListDown", "k": "ListUp", "l": "ListSelect", "h": "ListBack", "gg": "ListTop", "G": "ListBottom"`
+ */
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{Error, bail};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+use crate::event::AppEvent;
+
+/*
+ * Statics
+ */
+
+/// Bindings used when the config has none
+const DEFAULT_BINDINGS: [(&str, &str); 29] = [
+    // Quit
+    ("q", "Quit"),
+    // Volume
+    ("ctrl+up", "VolumeUp"),
+    ("ctrl+down", "VolumeDown"),
+    // Queue
+    ("shift+up", "QueueMoveUp"),
+    ("shift+down", "QueueMoveDown"),
+    ("delete", "QueueRemove"),
+    ("d", "QueueRemove"),
+    ("X", "QueueClear"),
+    ("w", "QueueSave"),
+    // List
+    ("up", "ListUp"),
+    ("down", "ListDown"),
+    ("tab", "ListQueue"),
+    ("backtab", "ListQueueNext"),
+    ("enter", "ListSelect"),
+    ("esc", "ListBack"),
+    ("u", "ListShowQueue"),
+    ("i", "ListShowDiagnostics"),
+    // Search
+    ("/", "SearchOpen"),
+    // Playlist Files
+    ("n", "PlaylistCreate"),
+    ("e", "PlaylistRename"),
+    ("D", "PlaylistDelete"),
+    ("a", "PlaylistAdd"),
+    // Playback
+    ("space", "PlayTogle"),
+    ("ctrl+right", "PlayNext"),
+    ("ctrl+left", "PlayPrevious"),
+    ("right", "PlaySeekForward"),
+    ("left", "PlaySeekBack"),
+    ("r", "PlayCycleRepeat"),
+    ("s", "PlayCycleShuffle"),
+];
+
+/*
+ * Data
+ */
+
+/// A key with its modifiers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+/// Keys resolved to events, remembers the start of a sequence between key presses
+pub struct Keymap {
+    bindings: Vec<(Vec<Chord>, AppEvent)>,
+    /// Keys pressed so far of a sequence like `gg`
+    pending: Vec<Chord>,
+}
+
+/*
+ * Functions
+ */
+
+/// Bindings written to new configs
+pub fn default_bindings() -> BTreeMap<String, String> {
+    DEFAULT_BINDINGS
+        .iter()
+        .map(|(key, action)| (key.to_string(), action.to_string()))
+        .collect()
+}
+
+/// Key press written like a binding, how attached terminals send keys
+pub fn key_name(key_event: KeyEvent) -> Option<String> {
+    Chord::from_event(key_event).name()
+}
+
+/// Key press from a name written by [`key_name`]
+pub fn parse_key(name: &str) -> Option<KeyEvent> {
+    let chord = Chord::parse(name).ok()?;
+    Some(KeyEvent::new(chord.code, chord.modifiers))
+}
+
+impl Keymap {
+    /// Parses bindings from the config, every problem is reported at once
+    pub fn parse(bindings: &BTreeMap<String, String>) -> Result<Self, Error> {
+        let mut parsed = Vec::new();
+        let mut errors = Vec::new();
+
+        for (key, action) in bindings {
+            let event = match action_from_name(action) {
+                Some(v) => v,
+                None => {
+                    errors.push(format!("`{key}`: unknown action `{action}`"));
+                    continue;
+                }
+            };
+            match parse_sequence(key) {
+                Ok(sequence) => parsed.push((key, sequence, event)),
+                Err(e) => errors.push(format!("`{key}`: {e}")),
+            }
+        }
+
+        // A key that starts a longer sequence would never get to finish it
+        for (key, sequence, _) in &parsed {
+            for (other_key, other, _) in &parsed {
+                if other.len() > sequence.len() && other.starts_with(sequence) {
+                    errors.push(format!(
+                        "`{key}` blocks `{other_key}`, which starts with it"
+                    ));
+                }
+                if other == sequence && key < other_key {
+                    errors.push(format!("`{key}` & `{other_key}` are the same keys"));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("Invalid keymap in config:\n  {}", errors.join("\n  "));
+        }
+
+        Ok(Self {
+            bindings: parsed
+                .into_iter()
+                .map(|(_, sequence, event)| (sequence, event))
+                .collect(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Event for a key press, `None` while a sequence isn't finished or the key isn't bound
+    pub fn resolve(&mut self, key_event: KeyEvent) -> Option<AppEvent> {
+        // Some terminals report releases too
+        if key_event.kind == KeyEventKind::Release {
+            return None;
+        }
+
+        let chord = Chord::from_event(key_event);
+        self.pending.push(chord);
+
+        if let Some(event) = self.lookup() {
+            return event;
+        }
+
+        // Not part of any sequence, the key may still start or be one on its own
+        self.pending = vec![chord];
+        match self.lookup() {
+            Some(event) => event,
+            None => {
+                self.pending.clear();
+                None
+            }
+        }
+    }
+
+    /// Whether the pending keys finish a binding (`Some(Some)`) or start one (`Some(None)`)
+    fn lookup(&mut self) -> Option<Option<AppEvent>> {
+        let mut is_prefix = false;
+
+        for (sequence, event) in &self.bindings {
+            if *sequence == self.pending {
+                self.pending.clear();
+                return Some(Some(event.clone()));
+            }
+            if sequence.starts_with(&self.pending) {
+                is_prefix = true;
+            }
+        }
+
+        match is_prefix {
+            true => Some(None),
+            false => None,
+        }
+    }
+}
+
+impl Chord {
+    /// Chord as compared against bindings
+    fn from_event(key_event: KeyEvent) -> Self {
+        Self::new(key_event.code, key_event.modifiers)
+    }
+
+    /// Shift is already part of characters like `G` & of back tab, drop it so they match either way
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+
+        Self { code, modifiers }
+    }
+
+    /// Written the way [`Chord::parse`] reads it, `None` for keys bindings can't name
+    fn name(&self) -> Option<String> {
+        let key = match self.code {
+            KeyCode::Char(' ') => "space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::F(number) => format!("f{number}"),
+            KeyCode::Up => "up".to_string(),
+            KeyCode::Down => "down".to_string(),
+            KeyCode::Left => "left".to_string(),
+            KeyCode::Right => "right".to_string(),
+            KeyCode::Home => "home".to_string(),
+            KeyCode::End => "end".to_string(),
+            KeyCode::PageUp => "pageup".to_string(),
+            KeyCode::PageDown => "pagedown".to_string(),
+            KeyCode::Tab => "tab".to_string(),
+            KeyCode::BackTab => "backtab".to_string(),
+            KeyCode::Enter => "enter".to_string(),
+            KeyCode::Esc => "esc".to_string(),
+            KeyCode::Backspace => "backspace".to_string(),
+            KeyCode::Delete => "delete".to_string(),
+            KeyCode::Insert => "insert".to_string(),
+            _ => return None,
+        };
+
+        let mut name = String::new();
+        for (modifier, label) in [
+            (KeyModifiers::CONTROL, "ctrl+"),
+            (KeyModifiers::ALT, "alt+"),
+            (KeyModifiers::SHIFT, "shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                name.push_str(label);
+            }
+        }
+        name.push_str(&key);
+
+        Some(name)
+    }
+
+    /// Parses a single chord like `ctrl+up`, `shift+x` or `/`
+    fn parse(value: &str) -> Result<Self, String> {
+        // `+` on its own or at the end (`ctrl++`) is the key itself
+        let (modifier_part, key) = match value.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest, "+"),
+            _ => match value.rsplit_once('+') {
+                Some(v) => v,
+                None => ("", value),
+            },
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in modifier_part.split('+').filter(|v| !v.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "shift" => KeyModifiers::SHIFT,
+                "alt" => KeyModifiers::ALT,
+                _ => return Err(format!("unknown modifier `{modifier}`")),
+            };
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => match modifiers.contains(KeyModifiers::SHIFT) {
+                true => KeyCode::Char(c.to_ascii_uppercase()),
+                false => KeyCode::Char(c),
+            },
+            _ => match key.to_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                name => match name.strip_prefix('f').and_then(|v| v.parse().ok()) {
+                    Some(number) => KeyCode::F(number),
+                    None => return Err(format!("unknown key `{key}`")),
+                },
+            },
+        };
+
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+/// Sequence of chords, split by spaces or written together like `gg`
+fn parse_sequence(value: &str) -> Result<Vec<Chord>, String> {
+    let mut sequence = Vec::new();
+
+    for part in value.split_whitespace() {
+        match Chord::parse(part) {
+            Ok(chord) => sequence.push(chord),
+            // Plain characters written together, like `gg`
+            Err(e) => match part.chars().all(|c| c.is_ascii_graphic() && c != '+') {
+                true => {
+                    for c in part.chars() {
+                        sequence.push(Chord::new(KeyCode::Char(c), KeyModifiers::NONE));
+                    }
+                }
+                false => return Err(e),
+            },
+        }
+    }
+
+    match sequence.is_empty() {
+        true => Err("no key given".to_string()),
+        false => Ok(sequence),
+    }
+}
+
+/// Events that can be bound, those carrying typed input can't
+fn action_from_name(name: &str) -> Option<AppEvent> {
+    let event = match name {
+        "Quit" => AppEvent::Quit,
+        "ListUp" => AppEvent::ListUp,
+        "ListDown" => AppEvent::ListDown,
+        "ListTop" => AppEvent::ListTop,
+        "ListBottom" => AppEvent::ListBottom,
+        "ListQueue" => AppEvent::ListQueue,
+        "ListQueueNext" => AppEvent::ListQueueNext,
+        "ListSelect" => AppEvent::ListSelect,
+        "ListBack" => AppEvent::ListBack,
+        "ListShowQueue" => AppEvent::ListShowQueue,
+        "ListShowDiagnostics" => AppEvent::ListShowDiagnostics,
+        "SearchOpen" => AppEvent::SearchOpen,
+        "PlaylistCreate" => AppEvent::PlaylistCreate,
+        "PlaylistRename" => AppEvent::PlaylistRename,
+        "PlaylistDelete" => AppEvent::PlaylistDelete,
+        "PlaylistAdd" => AppEvent::PlaylistAdd,
+        "QueueRemove" => AppEvent::QueueRemove,
+        "QueueMoveUp" => AppEvent::QueueMoveUp,
+        "QueueMoveDown" => AppEvent::QueueMoveDown,
+        "QueueClear" => AppEvent::QueueClear,
+        "QueueSave" => AppEvent::QueueSave,
+        // Both spellings, the event name has a typo
+        "PlayTogle" | "PlayToggle" => AppEvent::PlayTogle,
+        "PlayNext" => AppEvent::PlayNext,
+        "PlayPrevious" => AppEvent::PlayPrevious,
+        "PlaySeekForward" => AppEvent::PlaySeekForward,
+        "PlaySeekBack" => AppEvent::PlaySeekBack,
+        "PlayCycleRepeat" => AppEvent::PlayCycleRepeat,
+        "PlayCycleShuffle" => AppEvent::PlayCycleShuffle,
+        "VolumeUp" => AppEvent::VolumeUp,
+        "VolumeDown" => AppEvent::VolumeDown,
+        _ => return None,
+    };
+
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(bindings: &[(&str, &str)]) -> Result<Keymap, Error> {
+        Keymap::parse(
+            &bindings
+                .iter()
+                .map(|(key, action)| (key.to_string(), action.to_string()))
+                .collect(),
+        )
+    }
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn defaults_parse() {
+        Keymap::parse(&default_bindings()).unwrap();
+    }
+
+    #[test]
+    fn plus_is_a_key() {
+        assert_eq!(
+            Chord::parse("ctrl++"),
+            Ok(Chord::new(KeyCode::Char('+'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            Chord::parse("+"),
+            Ok(Chord::new(KeyCode::Char('+'), KeyModifiers::NONE))
+        );
+        assert!(Chord::parse("hyper+x").is_err());
+    }
+
+    #[test]
+    fn shift_matches_the_capital() {
+        let mut keymap = keymap(&[("shift+x", "QueueClear")]).unwrap();
+
+        // Terminals send `X`, with or without shift
+        let event = keymap.resolve(press(KeyCode::Char('X'), KeyModifiers::SHIFT));
+        assert!(matches!(event, Some(AppEvent::QueueClear)));
+        let event = keymap.resolve(press(KeyCode::Char('X'), KeyModifiers::NONE));
+        assert!(matches!(event, Some(AppEvent::QueueClear)));
+        assert!(
+            keymap
+                .resolve(press(KeyCode::Char('x'), KeyModifiers::NONE))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sequences_wait_for_the_next_key() {
+        let mut keymap = keymap(&[("gg", "ListTop"), ("G", "ListBottom")]).unwrap();
+        let g = press(KeyCode::Char('g'), KeyModifiers::NONE);
+
+        assert!(keymap.resolve(g).is_none());
+        assert!(matches!(keymap.resolve(g), Some(AppEvent::ListTop)));
+
+        // A key that doesn't continue the sequence is taken on its own
+        assert!(keymap.resolve(g).is_none());
+        let event = keymap.resolve(press(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert!(matches!(event, Some(AppEvent::ListBottom)));
+    }
+
+    #[test]
+    fn sequences_with_spaces() {
+        assert_eq!(parse_sequence("g t"), parse_sequence("gt"));
+        assert_eq!(
+            parse_sequence("ctrl+x ctrl+s").unwrap(),
+            [
+                Chord::new(KeyCode::Char('x'), KeyModifiers::CONTROL),
+                Chord::new(KeyCode::Char('s'), KeyModifiers::CONTROL),
+            ]
+        );
+        assert!(parse_sequence(" ").is_err());
+    }
+
+    #[test]
+    fn prefix_is_rejected() {
+        let e = keymap(&[("g", "ListTop"), ("gg", "ListTop")])
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("`g` blocks `gg`"));
+    }
+
+    #[test]
+    fn duplicate_is_rejected() {
+        let e = keymap(&[("X", "QueueClear"), ("shift+x", "QueueClear")])
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("are the same keys"));
+    }
+
+    #[test]
+    fn unknown_action_is_rejected() {
+        let e = keymap(&[("q", "Quitt")]).err().unwrap();
+        assert!(e.to_string().contains("unknown action `Quitt`"));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for name in ["ctrl+up", "space", "G", "ctrl++", "f5", "alt+enter"] {
+            let key = parse_key(name).unwrap();
+            assert_eq!(key_name(key).as_deref(), Some(name));
+        }
+    }
+}
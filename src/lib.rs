@@ -1,7 +1,26 @@
 pub mod app;
+#[cfg(unix)]
+pub mod attach;
 pub mod audio;
+pub mod cli;
 pub mod config;
+pub mod control;
+pub mod cue;
+pub mod doctor;
 pub mod event;
 pub mod files;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod keymap;
+pub mod library;
+pub mod loudness;
+#[cfg(feature = "mpris")]
+pub mod mpris;
+pub mod naming;
+pub mod playlist_file;
+pub mod queue;
+pub mod search;
+pub mod session;
 pub mod ui;
 pub mod unzip;
+pub mod watcher;
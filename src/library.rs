@@ -0,0 +1,242 @@
+/*
+ * Handles the on-disk library index so tags are only read once
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use color_eyre::eyre::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cue,
+    files::{self, Playlist, Track, TrackMetadata},
+};
+
+/*
+ * Statics
+ */
+
+/// Index file name, saved in the source directory
+pub const INDEX_FILE: &str = "tplayer_library.json";
+/// Bumped when [`TrackMetadata`] gains fields so old entries get read again
+const INDEX_VERSION: u32 = 3;
+
+/*
+ * Data
+ */
+
+/// Parsed metadata of every track, keyed by path
+#[derive(Serialize, Deserialize, Default)]
+pub struct LibraryIndex {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Files found during this scan, anything else gets pruned
+    #[serde(skip)]
+    seen: HashSet<PathBuf>,
+    /// Whether the index needs saving
+    #[serde(skip)]
+    changed: bool,
+    #[serde(default)]
+    version: u32,
+    entries: HashMap<PathBuf, IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IndexEntry {
+    /// Modified time in seconds, used with size to spot changed files
+    pub mtime: u64,
+    pub size: u64,
+    pub metadata: TrackMetadata,
+}
+
+impl LibraryIndex {
+    /*
+     * Init
+     */
+
+    /// Loads the index in the source directory, empty if missing, unreadable or outdated
+    pub fn load(source: &Path) -> Self {
+        let path = source.join(INDEX_FILE);
+        let mut index: LibraryIndex = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).unwrap_or_default(),
+            Err(_) => LibraryIndex::default(),
+        };
+
+        if index.version != INDEX_VERSION {
+            index = LibraryIndex {
+                version: INDEX_VERSION,
+                changed: true,
+                ..Default::default()
+            };
+        }
+
+        index.path = path;
+        index
+    }
+
+    /*
+     * Scanning
+     */
+
+    /// Tracks in a playlist folder sorted by number, only reading tags of new or changed files
+    pub fn scan_playlist(&mut self, playlist: &Playlist) -> Vec<Track> {
+        let mut tracks = self.scan_folder(&playlist.path, playlist);
+        files::sort_tracks(&mut tracks);
+        tracks
+    }
+
+    /// Every track below a folder at any depth, in path order
+    ///
+    /// Tracks aren't part of a playlist yet, so `playlist_index` is left for the caller to set
+    pub fn scan_tree(&mut self, path: &Path) -> Vec<Track> {
+        let mut tracks = Vec::new();
+        let mut folders = vec![path.to_path_buf()];
+
+        while let Some(folder) = folders.pop() {
+            // Untagged artists fall back to the folder above, as in `Artist/Album/01 - Title.flac`
+            let artists = folder
+                .parent()
+                .filter(|v| v.starts_with(path) && *v != path)
+                .and_then(|v| v.file_name())
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let playlist = Playlist::new(String::new(), artists, folder.clone(), 0);
+            tracks.extend(self.scan_folder(&folder, &playlist));
+
+            if let Ok(children) = fs::read_dir(&folder) {
+                folders.extend(
+                    children
+                        .filter_map(|child| child.ok())
+                        .filter(|child| child.file_type().is_ok_and(|v| v.is_dir()))
+                        .map(|child| child.path()),
+                );
+            }
+        }
+
+        tracks.sort_by(|a, b| a.path.cmp(&b.path));
+        tracks
+    }
+
+    /// Tracks directly in a folder, unsorted
+    ///
+    /// Files described by a cue sheet are split into a track per cue entry
+    fn scan_folder(&mut self, folder: &Path, playlist: &Playlist) -> Vec<Track> {
+        let paths: Vec<PathBuf> = match fs::read_dir(folder) {
+            Ok(v) => v
+                .filter_map(|child| child.ok()) // Is able to read
+                .filter(|child| child.file_type().is_ok_and(|v| v.is_file()))
+                .map(|child| child.path())
+                .collect(),
+            Err(_) => return Vec::new(),
+        };
+
+        let mut tracks = Vec::new();
+        let mut covered = HashSet::new();
+        for sheet in paths
+            .iter()
+            .filter(|v| cue::is_cue(v))
+            .filter_map(|v| cue::parse(v))
+        {
+            for cue_track in &sheet.tracks {
+                let file = match cue::find_audio_file(&cue_track.file) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let track = match self.scan_file(file.clone(), playlist) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                covered.insert(file);
+                tracks.push(Track::from_cue(track, &sheet, cue_track));
+            }
+        }
+
+        for path in paths {
+            if covered.contains(&path) {
+                continue;
+            }
+            tracks.extend(self.scan_file(path, playlist));
+        }
+
+        tracks
+    }
+
+    /// A single file, from the index if it hasn't changed
+    pub fn scan_file(&mut self, path: PathBuf, playlist: &Playlist) -> Option<Track> {
+        if !files::is_audio(&path) {
+            return None;
+        }
+
+        let (mtime, size) = file_stamp(&path)?;
+        self.seen.insert(path.clone());
+
+        // Unchanged since last scan, otherwise read tags
+        let metadata = match self.entries.get(&path) {
+            Some(entry) if entry.mtime == mtime && entry.size == size => entry.metadata.clone(),
+            _ => {
+                let metadata = files::read_track_metadata(&path);
+                self.entries.insert(
+                    path.clone(),
+                    IndexEntry {
+                        mtime,
+                        size,
+                        metadata: metadata.clone(),
+                    },
+                );
+                self.changed = true;
+                metadata
+            }
+        };
+
+        // Stored without the playlist's artists, the same file can be in other playlists
+        let mut track = Track {
+            playlist_index: playlist.id,
+            path,
+            metadata,
+            cue: None,
+        };
+        track.metadata.fill_artists(&playlist.artists);
+        Some(track)
+    }
+
+    /*
+     * Cleanup
+     */
+
+    /// Drops files that weren't seen this scan
+    pub fn prune(&mut self) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| self.seen.contains(path));
+        self.changed |= self.entries.len() != before;
+    }
+
+    /// Saves index to main path if anything changed
+    pub fn save(&mut self) -> Result<(), Error> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        fs::write(&self.path, serde_json::to_string(self)?)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Modified time & size of a file
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+
+    Some((mtime, metadata.len()))
+}
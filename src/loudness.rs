@@ -0,0 +1,248 @@
+/*
+ * Handles loudness analysis for tracks without ReplayGain tags
+ */
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, mpsc},
+    thread,
+    time::UNIX_EPOCH,
+};
+
+use color_eyre::eyre::{Error, bail};
+use ebur128::{EbuR128, Mode};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+use crate::files::{Playlist, SourceHandler, Track};
+
+/*
+ * Statics
+ */
+
+/// Sidecar cache file name, saved in the source directory
+pub const CACHE_FILE: &str = "tplayer_loudness.json";
+/// ReplayGain 2.0 reference level
+const REFERENCE_LUFS: f64 = -18.0;
+/// Frames handed to the analyzer at once
+const CHUNK_FRAMES: usize = 4096;
+/// How many analyzed tracks before the worker saves progress
+const SAVE_INTERVAL: usize = 20;
+
+/// Loaded cache so playback can look up results
+static CACHE: OnceLock<Mutex<LoudnessCache>> = OnceLock::new();
+/// Hands playlists to the background worker once it's running
+static WORKER: OnceLock<mpsc::Sender<Vec<Playlist>>> = OnceLock::new();
+
+/*
+ * Data
+ */
+
+/// Results of analysing a single file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Loudness {
+    /// Modified time of the file when analyzed, in seconds
+    pub mtime: u64,
+    /// EBU R128 integrated loudness in LUFS
+    pub integrated: f64,
+    /// Linear true peak across all channels
+    pub true_peak: f64,
+}
+
+/// Analysis results keyed by path
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct LoudnessCache {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: HashMap<PathBuf, Loudness>,
+}
+
+impl Loudness {
+    /// ReplayGain style gain in dB
+    pub fn gain(&self) -> f32 {
+        (REFERENCE_LUFS - self.integrated) as f32
+    }
+}
+
+impl LoudnessCache {
+    /// Loads the cache in the source directory, empty if missing or unreadable
+    pub fn load(source: &Path) -> Self {
+        let path = source.join(CACHE_FILE);
+        let mut cache: LoudnessCache = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).unwrap_or_default(),
+            Err(_) => LoudnessCache::default(),
+        };
+
+        cache.path = path;
+        cache
+    }
+
+    /// Cached result, None if missing or the file has changed since
+    pub fn get(&self, path: &Path) -> Option<Loudness> {
+        let entry = self.entries.get(path)?;
+        (Some(entry.mtime) == mtime(path)).then_some(*entry)
+    }
+
+    pub fn insert(&mut self, path: PathBuf, loudness: Loudness) {
+        self.entries.insert(path, loudness);
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        fs::write(&self.path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/*
+ * Shared Cache
+ */
+
+/// Loads the cache so playback can use it
+pub fn init(source: &Path) {
+    let _ = CACHE.set(Mutex::new(LoudnessCache::load(source)));
+}
+
+/// Looks up a track in the shared cache
+pub fn lookup(path: &Path) -> Option<Loudness> {
+    CACHE.get()?.lock().unwrap().get(path)
+}
+
+/// Analyzes tracks missing gain tags in the background, filling the shared cache
+///
+/// Carries on with playlists given to [`analyze_later`]
+pub fn spawn_worker(playlists: Vec<Playlist>) {
+    let (sender, receiver) = mpsc::channel();
+    let _ = sender.send(playlists);
+    if WORKER.set(sender).is_err() {
+        return;
+    }
+
+    thread::spawn(move || {
+        let cache = match CACHE.get() {
+            Some(v) => v,
+            None => return,
+        };
+
+        for playlists in receiver {
+            let mut analyzed = 0;
+            for track in playlists.iter().flat_map(|playlist| playlist.iter()) {
+                if !needs_analysis(track) || cache.lock().unwrap().get(&track.path).is_some() {
+                    continue;
+                }
+
+                // Don't hold the lock while decoding, playback needs it
+                let loudness = match analyze(&track.path) {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+
+                let mut cache = cache.lock().unwrap();
+                cache.insert(track.path.clone(), loudness);
+                analyzed += 1;
+                if analyzed % SAVE_INTERVAL == 0 {
+                    let _ = cache.save();
+                }
+            }
+
+            if analyzed > 0 {
+                let _ = cache.lock().unwrap().save();
+            }
+        }
+    });
+}
+
+/// Hands playlists to the worker, like ones a rescan found, ignored if it isn't running
+pub fn analyze_later(playlists: Vec<Playlist>) {
+    if let Some(worker) = WORKER.get() {
+        let _ = worker.send(playlists);
+    }
+}
+
+/*
+ * Analysis
+ */
+
+/// Analyzes every track missing gain tags, used by `tplayer analyze`
+pub fn analyze_library(source: &SourceHandler) -> Result<(), Error> {
+    let mut cache = LoudnessCache::load(&source.path);
+    let mut playlists: Vec<&Playlist> = source.playlists.values().collect();
+    playlists.sort_by_key(|playlist| playlist.id);
+
+    for playlist in playlists {
+        for track in playlist.tracks() {
+            if !needs_analysis(&track) || cache.get(&track.path).is_some() {
+                continue;
+            }
+
+            match analyze(&track.path) {
+                Ok(loudness) => {
+                    println!(
+                        "{:6.1} LUFS {:6.2} dB  {}",
+                        loudness.integrated,
+                        loudness.gain(),
+                        track.path.display()
+                    );
+                    cache.insert(track.path.clone(), loudness);
+                }
+                Err(e) => eprintln!("Failed to analyze `{}`: {e}", track.path.display()),
+            }
+        }
+
+        // Save after each playlist so an interrupted run isn't wasted
+        cache.save()?;
+    }
+
+    Ok(())
+}
+
+/// Tracks without ReplayGain tags
+pub fn needs_analysis(track: &Track) -> bool {
+    track.metadata.track_gain.is_none() && track.metadata.album_gain.is_none()
+}
+
+/// Decodes a file & measures integrated loudness and true peak
+pub fn analyze(path: &Path) -> Result<Loudness, Error> {
+    let mtime = mtime(path).unwrap_or_default();
+    let decoder = rodio::Decoder::try_from(File::open(path)?)?;
+    let channels = decoder.channels() as u32;
+
+    let mut meter = EbuR128::new(channels, decoder.sample_rate(), Mode::I | Mode::TRUE_PEAK)?;
+    let chunk_len = CHUNK_FRAMES * channels as usize;
+    let mut chunk = Vec::with_capacity(chunk_len);
+
+    for sample in decoder {
+        chunk.push(sample);
+        if chunk.len() == chunk_len {
+            meter.add_frames_f32(&chunk)?;
+            chunk.clear();
+        }
+    }
+
+    // Leftover partial chunk, only whole frames
+    chunk.truncate(chunk.len() - chunk.len() % channels as usize);
+    meter.add_frames_f32(&chunk)?;
+
+    // Silent tracks have no meaningful gain
+    let integrated = meter.loudness_global()?;
+    if !integrated.is_finite() {
+        bail!("`{}` is silent", path.display());
+    }
+
+    let mut true_peak: f64 = 0.0;
+    for channel in 0..channels {
+        true_peak = true_peak.max(meter.true_peak(channel)?);
+    }
+
+    Ok(Loudness {
+        mtime,
+        integrated,
+        true_peak,
+    })
+}
+
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
@@ -1,21 +1,155 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+#[cfg(unix)]
+use color_eyre::eyre::bail;
 use std::{fs, path::PathBuf};
 
-use tplayer::{app::App, audio::AudioHandler, config::Config, files::SourceHandler, unzip};
+use tplayer::{
+    app::App,
+    audio::AudioHandler,
+    cli,
+    config::Config,
+    doctor,
+    files::SourceHandler,
+    keymap::Keymap,
+    loudness,
+    naming::NamingScheme,
+    session::{SESSION_FILE, Session},
+    unzip,
+};
+#[cfg(unix)]
+use tplayer::{
+    attach,
+    control::{self, Command as ControlCommand},
+};
 
 /// Terminal music player because GUIs don't like wayland
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     /// Source directory
-    #[arg(short, long, default_value = "~/Music/")]
+    #[arg(short, long, default_value = "~/Music/", global = true)]
     source: String,
+
+    /// Start fresh instead of restoring the last session
+    #[arg(long)]
+    no_resume: bool,
+
+    /// Play without a terminal, `attach` to show it in one
+    #[cfg(unix)]
+    #[arg(long)]
+    daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Measure loudness of tracks without ReplayGain tags
+    Analyze,
+
+    /// Report problems with the source directory
+    Doctor {
+        /// Output as JSON for scripting
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Control a running player through its socket
+    #[cfg(unix)]
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+
+    /// Show a running daemon in this terminal, quitting detaches
+    #[cfg(unix)]
+    Attach,
+
+    /// Play an album, playlist file or track without the TUI
+    Play {
+        /// Path, or album title
+        target: String,
+    },
+
+    /// Queue an album, playlist file or track in a running player
+    #[cfg(unix)]
+    Enqueue {
+        /// Path, or album title
+        target: String,
+    },
+
+    /// Print the library
+    List {
+        #[command(subcommand)]
+        what: ListWhat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ListWhat {
+    /// Albums & playlist files
+    Albums {
+        /// Output as JSON for scripting
+        #[arg(long)]
+        json: bool,
+    },
+    /// Every track, or those of an album, playlist file or track
+    Tracks {
+        /// Path, or album title
+        target: Option<String>,
+
+        /// Output as JSON for scripting
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[cfg(unix)]
+#[derive(Subcommand, Debug, Clone)]
+enum CtlAction {
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+    /// Seconds into the track, `+10` or `-10` to seek relative to now
+    Seek {
+        #[arg(allow_hyphen_values = true)]
+        time: String,
+    },
+    /// Volume between 0 and 1
+    Volume {
+        level: f32,
+    },
+    /// Queue a track, or a playlist by its folder or file
+    Enqueue {
+        path: PathBuf,
+    },
+    /// Show what's playing
+    Status {
+        /// Output as JSON for scripting
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop the player
+    Quit,
 }
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
     let args = Args::parse();
 
+    // Talks to a running player, nothing else to set up
+    #[cfg(unix)]
+    if let Some(Command::Ctl { action }) = &args.command {
+        return ctl(action.clone());
+    }
+    #[cfg(unix)]
+    if let Some(Command::Attach) = &args.command {
+        return attach::run();
+    }
+
     // I don't wanna write out the whole home directory, so fill it in
     let absolute_source = PathBuf::from(
         args.source.replace(
@@ -26,29 +160,180 @@ fn main() -> color_eyre::Result<()> {
                 .unwrap(),
         ),
     );
-    println!("Source directory set to `{}`", absolute_source.display());
+    eprintln!("Source directory set to `{}`", absolute_source.display());
 
     // Create directory if needed
     if !fs::exists(absolute_source.clone())? {
-        println!("Source Directory doesn't exist, Generating...");
+        eprintln!("Source Directory doesn't exist, Generating...");
         fs::create_dir_all(absolute_source.clone()).expect("Failed to generate directory");
     }
 
-    // Handle unzip files in source if any
-    unzip::ask_unzip(&absolute_source)?;
-
-    // Init Handlers
-    let source =
-        SourceHandler::build(absolute_source.clone()).map_err(|e| color_eyre::eyre::eyre!(e))?;
-    let audio = AudioHandler::new();
-
     // Init & Handle Config
     let config = Config::parse_or_new(&absolute_source.join("tplayer_config.json"));
+    let naming = NamingScheme::parse(&config.naming)?;
+    let keymap = Keymap::parse(&config.keymap)?;
+
+    // Subcommands that don't need the player, `play` carries on to set it up
+    let play = match args.command {
+        Some(Command::Analyze) => {
+            let source = SourceHandler::build(absolute_source.clone(), naming, config.library)
+                .map_err(|e| color_eyre::eyre::eyre!(e))?;
+            return loudness::analyze_library(&source);
+        }
+        Some(Command::Doctor { json }) => {
+            let issues = doctor::run(&absolute_source, &naming)?;
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&issues)?),
+                false => doctor::print(&issues),
+            }
+            return Ok(());
+        }
+        #[cfg(unix)]
+        Some(Command::Enqueue { target }) => {
+            let source = SourceHandler::build(absolute_source.clone(), naming, config.library)
+                .map_err(|e| color_eyre::eyre::eyre!(e))?;
+            return cli::enqueue(&cli::resolve(&source, &target)?);
+        }
+        Some(Command::List { what }) => {
+            let source = SourceHandler::build(absolute_source.clone(), naming, config.library)
+                .map_err(|e| color_eyre::eyre::eyre!(e))?;
+            match what {
+                ListWhat::Albums { json } => {
+                    let albums = cli::albums(&source);
+                    match json {
+                        true => println!("{}", serde_json::to_string_pretty(&albums)?),
+                        false => cli::print_albums(&albums),
+                    }
+                }
+                ListWhat::Tracks { target, json } => {
+                    let target = match target {
+                        Some(target) => Some(cli::resolve(&source, &target)?),
+                        None => None,
+                    };
+                    let tracks = cli::tracks(&source, target.as_ref());
+                    match json {
+                        true => println!("{}", serde_json::to_string_pretty(&tracks)?),
+                        false => cli::print_tracks(&tracks),
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Play { target }) => Some(target),
+        // Handled before touching the source directory
+        #[cfg(unix)]
+        Some(Command::Ctl { .. }) | Some(Command::Attach) => None,
+        None => None,
+    };
+
+    // Only unix has a socket to control a daemon through
+    #[cfg(unix)]
+    let daemon = args.daemon;
+    #[cfg(not(unix))]
+    let daemon = false;
+
+    // Handle unzip files in source if any, a daemon or script has nobody to ask
+    if !daemon && play.is_none() {
+        unzip::ask_unzip(&absolute_source)?;
+    }
+
+    // Init Handlers
+    let source = SourceHandler::build(absolute_source.clone(), naming, config.library)
+        .map_err(|e| color_eyre::eyre::eyre!(e))?;
+    let mut audio = AudioHandler::new();
     audio.sink.set_volume(config.volume);
+    audio.normalization = config.normalization;
+    // Gain already analysed for untagged tracks, `play` needs it as much as the UI
+    loudness::init(&absolute_source);
+
+    // Plays & exits, no session or UI involved
+    if let Some(target) = play {
+        let tracks = cli::resolve(&source, &target)?.tracks();
+        return cli::play(&mut audio, tracks);
+    }
+
+    // Fill in loudness for untagged tracks while playing
+    loudness::spawn_worker(source.playlists.values().cloned().collect());
+
+    // Last session, still parsed without resuming so it gets saved on quit
+    let session = Session::parse(&absolute_source.join(SESSION_FILE)).filter(|_| !args.no_resume);
+
+    // Run without UI until stopped
+    #[cfg(unix)]
+    if daemon {
+        let mut app = App::headless(source, audio, config, keymap, session)?;
+        app.events.watch_source(absolute_source);
+        return app.run_headless();
+    }
 
     // Run UI
     let terminal = ratatui::init();
-    let result = App::new(source, audio, config).run(terminal);
+    let mut app = App::new(source, audio, config, keymap, session);
+    app.events.watch_source(absolute_source);
+    let result = app.run(terminal);
     ratatui::restore();
     result
 }
+
+/// Sends a single command to the control socket & prints the reply
+#[cfg(unix)]
+fn ctl(action: CtlAction) -> color_eyre::Result<()> {
+    let mut json = false;
+    let command = match action {
+        CtlAction::Play => ControlCommand::Play,
+        CtlAction::Pause => ControlCommand::Pause,
+        CtlAction::Toggle => ControlCommand::Toggle,
+        CtlAction::Next => ControlCommand::Next,
+        CtlAction::Previous => ControlCommand::Previous,
+        CtlAction::Seek { time } => {
+            let seconds: f64 = match time.trim_start_matches('+').parse() {
+                Ok(v) => v,
+                Err(_) => bail!("`{time}` isn't a number of seconds"),
+            };
+            match time.starts_with(['+', '-']) {
+                true => ControlCommand::Seek {
+                    position: None,
+                    offset: Some(seconds),
+                },
+                false => ControlCommand::Seek {
+                    position: Some(seconds),
+                    offset: None,
+                },
+            }
+        }
+        CtlAction::Volume { level } => ControlCommand::Volume { level },
+        // The player may run from another directory
+        CtlAction::Enqueue { path } => ControlCommand::Enqueue {
+            path: fs::canonicalize(&path)?,
+        },
+        CtlAction::Status { json: as_json } => {
+            json = as_json;
+            ControlCommand::Status
+        }
+        CtlAction::Quit => ControlCommand::Quit,
+    };
+
+    let reply = control::send(&command)?;
+    if !reply.ok {
+        bail!("{}", reply.error.unwrap_or_default());
+    }
+
+    if let Some(status) = reply.status {
+        match json {
+            true => println!("{}", serde_json::to_string_pretty(&status)?),
+            false => match status.title {
+                Some(title) => println!(
+                    "[{}] {} - {} ({:.0}/{:.0}s)",
+                    status.state,
+                    status.artists.unwrap_or_default(),
+                    title,
+                    status.position,
+                    status.duration
+                ),
+                None => println!("[{}]", status.state),
+            },
+        }
+    }
+
+    Ok(())
+}
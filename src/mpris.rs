@@ -0,0 +1,606 @@
+/*
+ * Handles the MPRIS2 D-Bus interface so desktops can control playback
+ */
+
+use std::{
+    collections::{HashMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, mpsc},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Error;
+use zbus::{
+    blocking::{Connection, connection::Builder},
+    interface,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+use crate::{
+    audio::AudioHandler,
+    event::{AppEvent, Event},
+};
+
+/*
+ * Statics
+ */
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.tplayer";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// Reported when nothing is playing, reserved by the spec
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// Position drift allowed before it counts as a seek
+const SEEK_THRESHOLD: Duration = Duration::from_secs(1);
+
+/*
+ * Data
+ */
+
+/// Published interface, kept up to date by [`Mpris::update`]
+pub struct Mpris {
+    connection: Connection,
+    state: Arc<Mutex<State>>,
+    /// When the position was last read, to notice seeks
+    updated: Instant,
+}
+
+/// Snapshot of playback the D-Bus objects answer from
+#[derive(Clone, PartialEq)]
+struct State {
+    status: Status,
+    track: Option<TrackInfo>,
+    position: Duration,
+    volume: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(Clone, PartialEq)]
+struct TrackInfo {
+    /// D-Bus path made from the track's key
+    id: String,
+    title: String,
+    artists: String,
+    album: Option<String>,
+    album_artist: Option<String>,
+    number: u32,
+    length: Duration,
+    url: String,
+}
+
+/// `org.mpris.MediaPlayer2`
+struct Root {
+    sender: mpsc::Sender<Event>,
+}
+
+/// `org.mpris.MediaPlayer2.Player`
+struct Player {
+    state: Arc<Mutex<State>>,
+    sender: mpsc::Sender<Event>,
+}
+
+/*
+ * Functions
+ */
+
+impl Mpris {
+    /// Claims the bus name on the session bus, calls come in as [`Event::App`]
+    pub fn spawn(sender: mpsc::Sender<Event>) -> Result<Self, Error> {
+        Self::spawn_on(Builder::session()?, sender)
+    }
+
+    /// Claims the bus name on whichever bus the builder connects to
+    fn spawn_on(builder: Builder, sender: mpsc::Sender<Event>) -> Result<Self, Error> {
+        let state = Arc::new(Mutex::new(State {
+            status: Status::Stopped,
+            track: None,
+            position: Duration::ZERO,
+            volume: 1.0,
+        }));
+
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(
+                OBJECT_PATH,
+                Root {
+                    sender: sender.clone(),
+                },
+            )?
+            .serve_at(
+                OBJECT_PATH,
+                Player {
+                    state: state.clone(),
+                    sender,
+                },
+            )?
+            .build()?;
+
+        Ok(Self {
+            connection,
+            state,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Refreshes the snapshot, signalling whatever changed
+    pub fn update(&mut self, audio: &AudioHandler) {
+        let current = State::read(audio);
+        let previous = {
+            let mut state = self.state.lock().unwrap();
+            std::mem::replace(&mut *state, current.clone())
+        };
+        let elapsed = self.updated.elapsed();
+        self.updated = Instant::now();
+
+        let mut changed: HashMap<&str, Value> = HashMap::new();
+        if current.status != previous.status {
+            changed.insert("PlaybackStatus", Value::from(current.status.label()));
+        }
+        if current.track != previous.track {
+            changed.insert("Metadata", Value::from(current.metadata()));
+        }
+        if current.volume != previous.volume {
+            changed.insert("Volume", Value::from(current.volume));
+        }
+
+        // Errors only mean nobody is listening
+        if !changed.is_empty() {
+            let _ = self.connection.emit_signal(
+                None::<&str>,
+                OBJECT_PATH,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                &(PLAYER_INTERFACE, changed, Vec::<&str>::new()),
+            );
+        }
+
+        // Position isn't signalled as it changes, only jumps are
+        let expected = match previous.status {
+            Status::Playing => previous.position + elapsed,
+            _ => previous.position,
+        };
+        if current.track.is_some()
+            && current.track == previous.track
+            && current.position.abs_diff(expected) > SEEK_THRESHOLD
+        {
+            let _ = self.connection.emit_signal(
+                None::<&str>,
+                OBJECT_PATH,
+                PLAYER_INTERFACE,
+                "Seeked",
+                &(current.position.as_micros() as i64),
+            );
+        }
+    }
+}
+
+impl State {
+    fn read(audio: &AudioHandler) -> Self {
+        let current = audio.current_track.as_ref();
+
+        Self {
+            status: match (current, audio.sink.is_paused()) {
+                (None, _) => Status::Stopped,
+                (Some(_), true) => Status::Paused,
+                (Some(_), false) => Status::Playing,
+            },
+            track: current.map(|current| {
+                let track = &current.track;
+                TrackInfo {
+                    id: track_id(&track.key().to_string_lossy()),
+                    title: track.metadata.title.clone(),
+                    artists: track.metadata.artists.clone(),
+                    album: track.metadata.album.clone(),
+                    album_artist: track.metadata.album_artist.clone(),
+                    number: track.metadata.number,
+                    length: current.total_duration,
+                    url: format!("file://{}", track.path.display()),
+                }
+            }),
+            position: audio.sink.get_pos(),
+            volume: audio.volume() as f64,
+        }
+    }
+
+    /// `xesam` & `mpris` fields of the current track
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let mut metadata = HashMap::new();
+        let track = match &self.track {
+            Some(v) => v,
+            None => {
+                insert(&mut metadata, "mpris:trackid", object_path(NO_TRACK));
+                return metadata;
+            }
+        };
+
+        insert(&mut metadata, "mpris:trackid", object_path(&track.id));
+        insert(
+            &mut metadata,
+            "mpris:length",
+            Value::from(track.length.as_micros() as i64),
+        );
+        insert(
+            &mut metadata,
+            "xesam:title",
+            Value::from(track.title.clone()),
+        );
+        insert(
+            &mut metadata,
+            "xesam:artist",
+            Value::from(vec![track.artists.clone()]),
+        );
+        insert(
+            &mut metadata,
+            "xesam:trackNumber",
+            Value::from(track.number as i32),
+        );
+        insert(&mut metadata, "xesam:url", Value::from(track.url.clone()));
+        if let Some(album) = &track.album {
+            insert(&mut metadata, "xesam:album", Value::from(album.clone()));
+        }
+        if let Some(album_artist) = &track.album_artist {
+            insert(
+                &mut metadata,
+                "xesam:albumArtist",
+                Value::from(vec![album_artist.clone()]),
+            );
+        }
+
+        metadata
+    }
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Playing => "Playing",
+            Status::Paused => "Paused",
+            Status::Stopped => "Stopped",
+        }
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {
+        let _ = self.sender.send(Event::App(AppEvent::Quit));
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "tplayer".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    /*
+     * Methods
+     */
+
+    fn play_pause(&self) {
+        if self.status() != Status::Stopped {
+            self.send(AppEvent::PlayTogle);
+        }
+    }
+
+    /// Resumes rather than toggles, the status may be a tick behind
+    fn play(&self) {
+        if self.status() != Status::Stopped {
+            self.send(AppEvent::PlayResume);
+        }
+    }
+
+    /// Nothing to stop into, pausing is the closest
+    fn pause(&self) {
+        self.send(AppEvent::PlayPause);
+    }
+
+    fn stop(&self) {
+        self.pause();
+    }
+
+    fn next(&self) {
+        self.send(AppEvent::PlayNext);
+    }
+
+    fn previous(&self) {
+        self.send(AppEvent::PlayPrevious);
+    }
+
+    /// Relative seek in microseconds, past the end skips to the next track
+    fn seek(&self, offset: i64) {
+        let (position, length) = {
+            let state = self.state.lock().unwrap();
+            match &state.track {
+                Some(track) => (state.position, track.length),
+                None => return,
+            }
+        };
+
+        let target = position.as_micros() as i64 + offset;
+        match target > length.as_micros() as i64 {
+            true => self.send(AppEvent::PlayNext),
+            false => self.send(AppEvent::PlaySeekTo(
+                Duration::from_micros(target.max(0) as u64),
+                None,
+            )),
+        }
+    }
+
+    /// Absolute seek, ignored if the track changed since the caller looked
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        let length = {
+            let state = self.state.lock().unwrap();
+            match &state.track {
+                Some(track) if track.id == track_id.as_str() => track.length,
+                _ => return,
+            }
+        };
+
+        if position < 0 || position as u128 > length.as_micros() {
+            return;
+        }
+        self.send(AppEvent::PlaySeekTo(
+            Duration::from_micros(position as u64),
+            None,
+        ));
+    }
+
+    fn open_uri(&self, _uri: String) {}
+
+    /*
+     * Properties
+     */
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.status().label().to_string()
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        self.state.lock().unwrap().metadata()
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.state.lock().unwrap().volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) {
+        self.send(AppEvent::VolumeSet(volume.clamp(0.0, 1.0) as f32));
+    }
+
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        self.state.lock().unwrap().position.as_micros() as i64
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.status() != Status::Stopped
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.status() != Status::Stopped
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.status() != Status::Stopped
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+impl Player {
+    fn status(&self) -> Status {
+        self.state.lock().unwrap().status
+    }
+
+    fn send(&self, event: AppEvent) {
+        // The app quitting drops the receiver, nothing to do then
+        let _ = self.sender.send(Event::App(event));
+    }
+}
+
+/// Track ids have to be object paths, so hash the key into one
+fn track_id(key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+
+    format!("/org/tplayer/track/{:016x}", hasher.finish())
+}
+
+fn object_path(path: &str) -> Value<'static> {
+    // Only ever given the constants & paths from `track_id`, which are valid
+    Value::from(ObjectPath::try_from(path.to_string()).unwrap())
+}
+
+fn insert(metadata: &mut HashMap<String, OwnedValue>, key: &str, value: Value) {
+    if let Ok(value) = OwnedValue::try_from(value) {
+        metadata.insert(key.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    use super::*;
+
+    /// Private session bus, killed when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// `None` where `dbus-daemon` isn't installed or can't start
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+
+            // Exits straight away without a session config
+            let address = address.trim().to_string();
+            match address.is_empty() {
+                true => None,
+                false => Some(Self { daemon, address }),
+            }
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    fn calls_become_events() {
+        let bus = match Bus::start() {
+            Some(v) => v,
+            None => {
+                eprintln!("dbus-daemon not found, skipping");
+                return;
+            }
+        };
+        let (sender, receiver) = mpsc::channel();
+        let _mpris =
+            Mpris::spawn_on(Builder::address(bus.address.as_str()).unwrap(), sender).unwrap();
+
+        let client = Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        // Nothing playing
+        let reply = client
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(PLAYER_INTERFACE, "PlaybackStatus"),
+            )
+            .unwrap();
+        let status: OwnedValue = reply.body().deserialize().unwrap();
+        assert_eq!(String::try_from(status).unwrap(), "Stopped");
+
+        client
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(PLAYER_INTERFACE),
+                "Next",
+                &(),
+            )
+            .unwrap();
+        let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(event, Event::App(AppEvent::PlayNext)));
+
+        // Pausing twice stays paused
+        for _ in 0..2 {
+            client
+                .call_method(
+                    Some(BUS_NAME),
+                    OBJECT_PATH,
+                    Some(PLAYER_INTERFACE),
+                    "Pause",
+                    &(),
+                )
+                .unwrap();
+            let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(matches!(event, Event::App(AppEvent::PlayPause)));
+        }
+
+        // Seeking needs a track, so it's dropped
+        client
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(PLAYER_INTERFACE),
+                "Seek",
+                &(5_000_000i64),
+            )
+            .unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+}
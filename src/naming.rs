@@ -0,0 +1,397 @@
+/*
+ * Handles how playlist folders are named
+ */
+
+use std::{
+    fs::{self, DirEntry},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Error, bail};
+use lofty::{
+    file::TaggedFileExt,
+    tag::{Accessor, ItemKey},
+};
+
+use crate::files;
+
+/*
+ * Statics
+ */
+
+/// Matches the layout tplayer has always used
+pub const DEFAULT_PATTERN: &str = "{artist} - {album}";
+/// Pattern value that reads artist & album from tags instead of folder names
+const TAGS_PATTERN: &str = "tags";
+
+/*
+ * Data
+ */
+
+/// How playlists are found in the source directory
+///
+/// Written in the config as a pattern such as `{artist}/{year} - {album}`, each `/` being a
+/// nested folder, or `tags` to use every folder with audio in it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NamingScheme {
+    /// One level per folder depth, the last level being the playlist folder
+    Pattern(Vec<Level>),
+    Tags,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Level {
+    /// As written in the config, used in error messages
+    text: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Artist,
+    Album,
+    Year,
+    /// `{_}`, matches anything & throws it away
+    Ignore,
+}
+
+/// A folder that fits the scheme
+pub struct FoundPlaylist {
+    pub path: PathBuf,
+    pub title: String,
+    pub artists: String,
+}
+
+/// A folder that doesn't fit the scheme
+pub struct Rejected {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Values captured while matching folder names
+#[derive(Clone, Default)]
+struct Captures {
+    artist: Option<String>,
+    album: Option<String>,
+}
+
+/*
+ * Functions
+ */
+
+impl NamingScheme {
+    /// Parses a pattern from the config
+    pub fn parse(pattern: &str) -> Result<Self, Error> {
+        let pattern = pattern.trim();
+        if pattern.eq_ignore_ascii_case(TAGS_PATTERN) {
+            return Ok(NamingScheme::Tags);
+        }
+
+        let levels = pattern
+            .split('/')
+            .map(Level::parse)
+            .collect::<Result<Vec<Level>, Error>>()?;
+
+        // Playlists need something to be called
+        let has_album = levels
+            .iter()
+            .flat_map(|level| level.segments.iter())
+            .any(|segment| *segment == Segment::Field(Field::Album));
+        if !has_album {
+            bail!("Naming pattern `{pattern}` needs an {{album}}");
+        }
+
+        Ok(NamingScheme::Pattern(levels))
+    }
+
+    /// Walks the source directory for playlist folders, sorted by path
+    pub fn find_playlists(&self, source: &Path) -> (Vec<FoundPlaylist>, Vec<Rejected>) {
+        let mut found = Vec::new();
+        let mut rejected = Vec::new();
+
+        match self {
+            NamingScheme::Pattern(levels) => walk_pattern(
+                source,
+                levels,
+                Captures::default(),
+                &mut found,
+                &mut rejected,
+            ),
+            NamingScheme::Tags => walk_tags(source, &mut found),
+        }
+
+        (found, rejected)
+    }
+}
+
+impl Default for NamingScheme {
+    fn default() -> Self {
+        NamingScheme::parse(DEFAULT_PATTERN).unwrap()
+    }
+}
+
+impl Level {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let mut segments = Vec::new();
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = match rest.find('}') {
+                        Some(v) => v,
+                        None => bail!("Unclosed `{{` in naming pattern `{text}`"),
+                    };
+                    let field = match &rest[1..end] {
+                        "artist" => Field::Artist,
+                        "album" => Field::Album,
+                        "year" => Field::Year,
+                        "_" => Field::Ignore,
+                        other => bail!("Unknown field `{{{other}}}` in naming pattern `{text}`"),
+                    };
+
+                    // Nothing to tell where the first one stops
+                    if let Some(Segment::Field(_)) = segments.last() {
+                        bail!("Fields need text between them in naming pattern `{text}`");
+                    }
+
+                    segments.push(Segment::Field(field));
+                    rest = &rest[end + 1..];
+                }
+                Some(start) => {
+                    segments.push(Segment::Literal(rest[..start].to_string()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        if segments.is_empty() {
+            bail!("Empty folder in naming pattern");
+        }
+
+        Ok(Self {
+            text: text.to_string(),
+            segments,
+        })
+    }
+
+    /// Fills in captures if the whole name fits, fields can't be empty
+    fn matches(&self, name: &str, captures: &mut Captures) -> bool {
+        let mut rest = name;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(literal) => match rest.strip_prefix(literal.as_str()) {
+                    Some(v) => rest = v,
+                    None => return false,
+                },
+                Segment::Field(field) => {
+                    // Runs up to the next literal, or the end of the name
+                    let end = match self.segments.get(i + 1) {
+                        Some(Segment::Literal(literal)) => match rest.find(literal.as_str()) {
+                            Some(v) => v,
+                            None => return false,
+                        },
+                        _ => rest.len(),
+                    };
+
+                    let value = rest[..end].trim();
+                    rest = &rest[end..];
+                    if value.is_empty() {
+                        return false;
+                    }
+
+                    match field {
+                        Field::Artist => captures.artist = Some(value.to_string()),
+                        Field::Album => captures.album = Some(value.to_string()),
+                        Field::Year if value.parse::<u32>().is_err() => return false,
+                        Field::Year | Field::Ignore => {}
+                    }
+                }
+            }
+        }
+
+        rest.is_empty()
+    }
+}
+
+/// Sub directories sorted by name
+fn sorted_dirs(path: &Path) -> Vec<DirEntry> {
+    let mut children: Vec<DirEntry> = match fs::read_dir(path) {
+        Ok(v) => v
+            .filter_map(|child| child.ok())
+            .filter(|child| child.file_type().is_ok_and(|v| v.is_dir()))
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+
+    children.sort_by_key(|child| child.file_name());
+    children
+}
+
+/// Matches each folder depth against its level, the last level being playlists
+fn walk_pattern(
+    path: &Path,
+    levels: &[Level],
+    captures: Captures,
+    found: &mut Vec<FoundPlaylist>,
+    rejected: &mut Vec<Rejected>,
+) {
+    let (level, remaining) = match levels.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+
+    for child in sorted_dirs(path) {
+        let name = child.file_name().to_string_lossy().to_string();
+        let mut captures = captures.clone();
+
+        if !level.matches(&name, &mut captures) {
+            rejected.push(Rejected {
+                path: child.path(),
+                reason: format!("`{name}` doesn't match `{}`", level.text),
+            });
+            continue;
+        }
+
+        if !remaining.is_empty() {
+            walk_pattern(&child.path(), remaining, captures, found, rejected);
+            continue;
+        }
+
+        found.push(FoundPlaylist {
+            path: child.path(),
+            title: captures.album.unwrap_or_default(),
+            artists: captures.artist.unwrap_or_default(),
+        });
+    }
+}
+
+/// Every folder with audio in it, named from the tags of its first track
+fn walk_tags(path: &Path, found: &mut Vec<FoundPlaylist>) {
+    for child in sorted_dirs(path) {
+        let path = child.path();
+
+        if let Some(first) = first_audio_file(&path) {
+            let (album, artist) = read_album_tags(&first);
+            found.push(FoundPlaylist {
+                title: album.unwrap_or_else(|| child.file_name().to_string_lossy().to_string()),
+                artists: artist.unwrap_or_default(),
+                path: path.clone(),
+            });
+        }
+
+        walk_tags(&path, found);
+    }
+}
+
+fn first_audio_file(path: &Path) -> Option<PathBuf> {
+    let mut tracks: Vec<PathBuf> = fs::read_dir(path)
+        .ok()?
+        .filter_map(|child| child.ok())
+        .map(|child| child.path())
+        .filter(|path| path.is_file() && files::is_audio(path))
+        .collect();
+
+    tracks.sort();
+    tracks.into_iter().next()
+}
+
+/// Album & album artist, falling back to the track artist
+fn read_album_tags(path: &Path) -> (Option<String>, Option<String>) {
+    let tagged_file = match lofty::read_from_path(path) {
+        Ok(v) => v,
+        Err(_) => return (None, None),
+    };
+    let tag = match tagged_file.primary_tag().or(tagged_file.first_tag()) {
+        Some(v) => v,
+        None => return (None, None),
+    };
+
+    let album = tag.album().map(|v| v.to_string());
+    let artist = tag
+        .get_string(&ItemKey::AlbumArtist)
+        .map(|v| v.to_string())
+        .or(tag.artist().map(|v| v.to_string()));
+
+    (album, artist)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn captures(pattern: &str, name: &str) -> Option<(Option<String>, Option<String>)> {
+        let level = Level::parse(pattern).unwrap();
+        let mut captures = Captures::default();
+        match level.matches(name, &mut captures) {
+            true => Some((captures.artist, captures.album)),
+            false => None,
+        }
+    }
+
+    #[test]
+    fn patterns_parse() {
+        assert_eq!(NamingScheme::parse(" Tags ").unwrap(), NamingScheme::Tags);
+        assert!(NamingScheme::parse("{artist}/{year} - {album}").is_ok());
+
+        assert!(NamingScheme::parse("{artist}").is_err());
+        assert!(NamingScheme::parse("{artist}{album}").is_err());
+        assert!(NamingScheme::parse("{artist} - {name}").is_err());
+        assert!(NamingScheme::parse("{artist} - {album").is_err());
+        assert!(NamingScheme::parse("{artist}//{album}").is_err());
+    }
+
+    #[test]
+    fn fields_are_captured() {
+        assert_eq!(
+            captures("{artist} - {album}", "Some Artist - Album - Deluxe"),
+            Some((Some("Some Artist".into()), Some("Album - Deluxe".into())))
+        );
+        assert_eq!(
+            captures("[{_}] {album}", "[FLAC] Album"),
+            Some((None, Some("Album".into())))
+        );
+        assert_eq!(captures("{artist} - {album}", "No Separator"), None);
+        assert_eq!(captures("{artist} - {album}", " - Album"), None);
+    }
+
+    #[test]
+    fn years_are_numbers() {
+        assert!(captures("{year} - {album}", "1999 - Album").is_some());
+        assert!(captures("{year} - {album}", "Late - Album").is_none());
+    }
+
+    #[test]
+    fn nested_folders() {
+        let source = std::env::temp_dir().join(format!("tplayer-naming-{}", process::id()));
+        for folder in ["Artist/2001 - First", "Artist/Second", "Other/1999 - Third"] {
+            fs::create_dir_all(source.join(folder)).unwrap();
+        }
+
+        let scheme = NamingScheme::parse("{artist}/{year} - {album}").unwrap();
+        let (found, rejected) = scheme.find_playlists(&source);
+        fs::remove_dir_all(&source).unwrap();
+
+        let found: Vec<(&str, &str)> = found
+            .iter()
+            .map(|v| (v.artists.as_str(), v.title.as_str()))
+            .collect();
+        assert_eq!(found, [("Artist", "First"), ("Other", "Third")]);
+
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].path, source.join("Artist/Second"));
+    }
+}
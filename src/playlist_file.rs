@@ -0,0 +1,468 @@
+/*
+ * Handles reading & writing M3U and PLS playlist files
+ */
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Error, bail};
+
+use crate::files::Track;
+
+/*
+ * Statics
+ */
+
+/// Playlist formats read from the source directory
+const PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "pls"];
+
+/*
+ * Data
+ */
+
+/// A single line of a playlist
+pub struct Entry {
+    pub path: PathBuf,
+    /// Title from `#EXTINF` or `TitleN=`, usually `Artist - Title`
+    pub title: Option<String>,
+    /// Lines as written, with any `#` lines before it
+    pub raw: String,
+}
+
+/// Entry that isn't a track in the library, a missing file or a stream
+///
+/// Kept so saving an edited playlist doesn't drop it
+#[derive(Clone, Debug)]
+pub struct Unresolved {
+    /// [`Track::key`] of the track listed before it, None at the start
+    pub after: Option<PathBuf>,
+    pub raw: String,
+}
+
+/*
+ * Functions
+ */
+
+/// Whether a file is a playlist we can read
+pub fn is_playlist(path: &Path) -> bool {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some(v) => PLAYLIST_EXTENSIONS.contains(&v.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Playlist files directly in a folder, sorted by name
+pub fn find(folder: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(folder) {
+        Ok(v) => v
+            .filter_map(|child| child.ok())
+            .map(|child| child.path())
+            .filter(|path| path.is_file() && is_playlist(path))
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+
+    paths.sort();
+    paths
+}
+
+/// Reads entries in order, relative paths are resolved against the playlist's folder
+pub fn read(path: &Path) -> Result<Vec<Entry>, Error> {
+    // Plain M3U is often latin-1, don't fail on it
+    let contents = String::from_utf8_lossy(&fs::read(path)?).to_string();
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let extension = path
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match extension.as_str() {
+        "m3u" | "m3u8" => Ok(parse_m3u(&contents, base)),
+        "pls" => Ok(parse_pls(&contents, base)),
+        _ => bail!("`{}` isn't a playlist", path.display()),
+    }
+}
+
+/// Writes tracks as an extended M3U8, paths relative to the file where possible
+///
+/// Tracks split from a file by a cue sheet are left out, an entry can only point at a whole file
+///
+/// Returns how many were left out
+pub fn write_m3u8(path: &Path, tracks: &[Track]) -> Result<usize, Error> {
+    write_entries(path, tracks, &[])
+}
+
+fn write_entries(path: &Path, tracks: &[Track], unresolved: &[Unresolved]) -> Result<usize, Error> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut contents = String::from("#EXTM3U\n");
+    let mut written = vec![false; unresolved.len()];
+    let mut push_after = |contents: &mut String, after: Option<PathBuf>| {
+        for (i, entry) in unresolved.iter().enumerate() {
+            if !written[i] && entry.after == after {
+                contents.push_str(&entry.raw);
+                written[i] = true;
+            }
+        }
+    };
+
+    let mut skipped = 0;
+    push_after(&mut contents, None);
+    for track in tracks {
+        if track.cue.is_some() {
+            skipped += 1;
+            push_after(&mut contents, Some(track.key()));
+            continue;
+        }
+
+        let metadata = &track.metadata;
+        let entry_path = track.path.strip_prefix(base).unwrap_or(&track.path);
+
+        contents.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            metadata.total_duration.as_secs(),
+            metadata.artists,
+            metadata.title,
+            entry_path.display()
+        ));
+        push_after(&mut contents, Some(track.key()));
+    }
+
+    // What came after removed tracks
+    for (i, entry) in unresolved.iter().enumerate() {
+        if !written[i] {
+            contents.push_str(&entry.raw);
+        }
+    }
+
+    fs::write(path, contents)?;
+    Ok(skipped)
+}
+
+/// Creates an empty M3U8 in a folder, failing if the name is taken
+pub fn create(folder: &Path, name: &str) -> Result<PathBuf, Error> {
+    let path = folder.join(format!("{}.m3u8", check_name(name)?));
+    if path.exists() {
+        bail!("`{}` already exists", name.trim());
+    }
+
+    write_m3u8(&path, &[])?;
+    Ok(path)
+}
+
+/// Renames a playlist file keeping its extension, returns the new path
+pub fn rename(path: &Path, name: &str) -> Result<PathBuf, Error> {
+    // Names like `Vol. 2` would lose their end to `set_extension`
+    let extension = path.extension().and_then(|v| v.to_str()).unwrap_or("m3u8");
+    let new_path = path.with_file_name(format!("{}.{extension}", check_name(name)?));
+
+    if new_path == path {
+        return Ok(new_path);
+    }
+    if new_path.exists() {
+        bail!("`{}` already exists", name.trim());
+    }
+
+    fs::rename(path, &new_path)?;
+    Ok(new_path)
+}
+
+/// Writes edited tracks back along with the entries that weren't read as tracks
+///
+/// Only M3U files are written, PLS files are left as they are. Returns how many tracks were left
+/// out, see [`write_m3u8`]
+pub fn save(path: &Path, tracks: &[Track], unresolved: &[Unresolved]) -> Result<usize, Error> {
+    let is_pls = path
+        .extension()
+        .is_some_and(|v| v.eq_ignore_ascii_case("pls"));
+    if is_pls {
+        bail!(
+            "`{}` is a PLS file, only M3U playlists can be edited",
+            path.display()
+        );
+    }
+
+    write_entries(path, tracks, unresolved)
+}
+
+/// Trimmed name that's usable as a file name
+fn check_name(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Name can't be empty");
+    }
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("Name can't contain slashes or start with `.`");
+    }
+
+    Ok(name)
+}
+
+fn parse_m3u(contents: &str, base: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut title = None;
+    let mut raw = String::new();
+
+    for line in contents.lines().map(|v| v.trim()) {
+        // Written again on save
+        if line.is_empty() || line == "#EXTM3U" {
+            continue;
+        }
+        raw.push_str(line);
+        raw.push('\n');
+
+        // `#EXTINF:123,Artist - Title` describes the next path
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, v)| v.trim().to_string())
+                .filter(|v| !v.is_empty());
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        entries.push(Entry {
+            path: resolve(line, base),
+            title: title.take(),
+            raw: std::mem::take(&mut raw),
+        });
+    }
+
+    entries
+}
+
+fn parse_pls(contents: &str, base: &Path) -> Vec<Entry> {
+    // Keys are numbered & can come in any order
+    let mut paths: BTreeMap<u32, (PathBuf, String)> = BTreeMap::new();
+    let mut titles = BTreeMap::new();
+
+    for line in contents.lines().map(|v| v.trim()) {
+        let (key, value) = match line.split_once('=') {
+            Some(v) => v,
+            None => continue,
+        };
+
+        if let Some(number) = key.strip_prefix("File")
+            && let Ok(number) = number.parse::<u32>()
+        {
+            paths.insert(number, (resolve(value.trim(), base), line.to_string()));
+        } else if let Some(number) = key.strip_prefix("Title")
+            && let Ok(number) = number.parse::<u32>()
+        {
+            titles.insert(number, value.trim().to_string());
+        }
+    }
+
+    paths
+        .into_iter()
+        .map(|(number, (path, raw))| Entry {
+            path,
+            title: titles.remove(&number),
+            raw,
+        })
+        .collect()
+}
+
+/// Absolute, `file://` or relative to the playlist
+fn resolve(line: &str, base: &Path) -> PathBuf {
+    // URIs escape spaces & anything else outside ASCII
+    let line = match line.strip_prefix("file://") {
+        Some(v) => percent_decode(v.strip_prefix("localhost").unwrap_or(v)),
+        None => line.to_string(),
+    };
+    // Playlists made on windows
+    let line = line.replace('\\', "/");
+
+    let path = PathBuf::from(line);
+    match path.is_absolute() {
+        true => path,
+        false => base.join(path),
+    }
+}
+
+/// `%20` style escapes back to the bytes they stand for, invalid escapes are kept as they are
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match bytes[i] {
+            b'%' => bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process, time::Duration};
+
+    use super::*;
+    use crate::{cue::CueSpan, files::test_track as track};
+
+    #[test]
+    fn m3u_paths_are_relative_to_the_playlist() {
+        let contents = "#EXTM3U\n#EXTINF:180,Artist - Title\nAlbum/01 - Title.flac\n\n/music/Other/02.mp3\nfile:///music/Third/03.ogg\n";
+        let entries = parse_m3u(contents, Path::new("/music"));
+
+        let paths: Vec<&Path> = entries.iter().map(|v| v.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/music/Album/01 - Title.flac"),
+                Path::new("/music/Other/02.mp3"),
+                Path::new("/music/Third/03.ogg"),
+            ]
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("Artist - Title"));
+        assert_eq!(entries[1].title, None);
+    }
+
+    #[test]
+    fn file_uris_are_decoded() {
+        let contents = "file:///music/My%20Album/01%20-%20Caf%C3%A9.flac\nfile://localhost/music/100%.flac\n100%25.flac\n";
+        let entries = parse_m3u(contents, Path::new("/music"));
+
+        let paths: Vec<&Path> = entries.iter().map(|v| v.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/music/My Album/01 - Café.flac"),
+                Path::new("/music/100%.flac"),
+                // Only URIs are escaped
+                Path::new("/music/100%25.flac"),
+            ]
+        );
+    }
+
+    #[test]
+    fn m3u_windows_separators() {
+        let entries = parse_m3u("Album\\01.flac\n", Path::new("/music"));
+        assert_eq!(entries[0].path, Path::new("/music/Album/01.flac"));
+    }
+
+    #[test]
+    fn pls_ordered_by_number() {
+        let contents = "[playlist]\nFile2=Album/02.flac\nTitle2=Second\nFile1=/music/Album/01.flac\nNumberOfEntries=2\n";
+        let entries = parse_pls(contents, Path::new("/music"));
+
+        let paths: Vec<&Path> = entries.iter().map(|v| v.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/music/Album/01.flac"),
+                Path::new("/music/Album/02.flac"),
+            ]
+        );
+        assert_eq!(entries[0].title, None);
+        assert_eq!(entries[1].title.as_deref(), Some("Second"));
+    }
+
+    #[test]
+    fn names_are_checked() {
+        assert_eq!(check_name("  Mix  ").unwrap(), "Mix");
+        assert!(check_name(" ").is_err());
+        assert!(check_name("a/b").is_err());
+        assert!(check_name(".hidden").is_err());
+    }
+
+    #[test]
+    fn m3u_entries_keep_their_lines() {
+        let contents =
+            "#EXTM3U\n# Mine\n#EXTINF:180,Artist - Title\nhttp://radio/stream\nAlbum/01.flac\n";
+        let entries = parse_m3u(contents, Path::new("/music"));
+
+        assert_eq!(
+            entries[0].raw,
+            "# Mine\n#EXTINF:180,Artist - Title\nhttp://radio/stream\n"
+        );
+        assert_eq!(entries[1].raw, "Album/01.flac\n");
+    }
+
+    #[test]
+    fn saving_keeps_unresolved_entries() {
+        let folder = std::env::temp_dir().join(format!("tplayer-playlist-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("Mix.m3u8");
+        let pls = folder.join("Mix.pls");
+        fs::write(&pls, "[playlist]\nFile1=gone.flac\n").unwrap();
+
+        let unresolved = [
+            Unresolved {
+                after: None,
+                raw: "http://radio/stream\n".to_string(),
+            },
+            Unresolved {
+                after: Some(folder.join("a.flac")),
+                raw: "#EXTINF:10,Gone\ngone.flac\n".to_string(),
+            },
+            // Its track was removed
+            Unresolved {
+                after: Some(folder.join("removed.flac")),
+                raw: "missing.flac\n".to_string(),
+            },
+        ];
+        // Reordered
+        let tracks = [
+            track(folder.join("b.flac"), "B"),
+            track(folder.join("a.flac"), "A"),
+        ];
+        save(&path, &tracks, &unresolved).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+
+        let refused = save(&pls, &tracks, &unresolved);
+        let pls_contents = fs::read_to_string(&pls).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(
+            contents,
+            "#EXTM3U\nhttp://radio/stream\n#EXTINF:60,Artist - B\nb.flac\n#EXTINF:60,Artist - A\na.flac\n#EXTINF:10,Gone\ngone.flac\nmissing.flac\n"
+        );
+        assert!(refused.is_err());
+        assert_eq!(pls_contents, "[playlist]\nFile1=gone.flac\n");
+    }
+
+    #[test]
+    fn cue_tracks_are_left_out() {
+        let folder = std::env::temp_dir().join(format!("tplayer-queue-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("Queue.m3u8");
+
+        let mut split = track(folder.join("album.flac"), "Split");
+        split.cue = Some(CueSpan {
+            index: 2,
+            start: Duration::from_secs(180),
+            end: None,
+        });
+        let tracks = [track(folder.join("a.flac"), "A"), split];
+        let skipped = write_m3u8(&path, &tracks).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(skipped, 1);
+        assert_eq!(contents, "#EXTM3U\n#EXTINF:60,Artist - A\na.flac\n");
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * Handles the play queue
+ */
+
+use std::collections::VecDeque;
+
+use ratatui::text::Text;
+
+use crate::files::Track;
+
+/// Tracks waiting to be played, first in first out
+#[derive(Default, Clone)]
+pub struct Queue {
+    tracks: VecDeque<Track>,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+     * Adding & Taking
+     */
+
+    /// Adds a track to the end of the queue
+    pub fn append(&mut self, track: Track) {
+        self.tracks.push_back(track);
+    }
+
+    /// Adds a track so it plays before anything else queued
+    pub fn insert_next(&mut self, track: Track) {
+        self.tracks.push_front(track);
+    }
+
+    /// Takes the next track to play
+    pub fn pop(&mut self) -> Option<Track> {
+        self.tracks.pop_front()
+    }
+
+    /// Next track to play without taking it
+    pub fn peek(&self) -> Option<&Track> {
+        self.tracks.front()
+    }
+
+    /*
+     * Editing
+     */
+
+    pub fn remove(&mut self, index: usize) -> Option<Track> {
+        self.tracks.remove(index)
+    }
+
+    /// Moves a track one place earlier, returns its new index
+    pub fn move_up(&mut self, index: usize) -> usize {
+        if index == 0 || index >= self.tracks.len() {
+            return index;
+        }
+
+        self.tracks.swap(index, index - 1);
+        index - 1
+    }
+
+    /// Moves a track one place later, returns its new index
+    pub fn move_down(&mut self, index: usize) -> usize {
+        if index + 1 >= self.tracks.len() {
+            return index;
+        }
+
+        self.tracks.swap(index, index + 1);
+        index + 1
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+    }
+
+    /// Keeps tracks the closure returns true for, it can update them in place
+    pub fn retain(&mut self, f: impl FnMut(&mut Track) -> bool) {
+        self.tracks.retain_mut(f);
+    }
+
+    /*
+     * Fetchers
+     */
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter()
+    }
+
+    /// Lists out queued tracks to be displayed
+    pub fn display(&self) -> Vec<Text<'_>> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| Text::from(format!("{:2} {}", i + 1, track.metadata.title)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::files::test_track;
+
+    fn queue(titles: &[&str]) -> Queue {
+        let mut queue = Queue::new();
+        for title in titles {
+            queue.append(test_track(PathBuf::from(format!("{title}.flac")), title));
+        }
+        queue
+    }
+
+    fn titles(queue: &Queue) -> Vec<&str> {
+        queue
+            .iter()
+            .map(|track| track.metadata.title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn inserted_tracks_play_first() {
+        let mut queue = queue(&["a", "b"]);
+        queue.insert_next(test_track(PathBuf::from("c.flac"), "c"));
+        assert_eq!(titles(&queue), ["c", "a", "b"]);
+
+        let mut empty = Queue::new();
+        empty.insert_next(test_track(PathBuf::from("c.flac"), "c"));
+        assert_eq!(empty.peek().unwrap().metadata.title, "c");
+        assert_eq!(empty.pop().unwrap().metadata.title, "c");
+        assert!(empty.pop().is_none());
+    }
+
+    #[test]
+    fn removing_past_the_end_does_nothing() {
+        let mut queue = queue(&["a", "b", "c"]);
+        assert_eq!(queue.remove(1).unwrap().metadata.title, "b");
+        assert!(queue.remove(2).is_none());
+        assert_eq!(titles(&queue), ["a", "c"]);
+    }
+
+    #[test]
+    fn moves_stop_at_the_ends() {
+        let mut queue = queue(&["a", "b", "c"]);
+
+        assert_eq!(queue.move_up(0), 0);
+        assert_eq!(queue.move_down(2), 2);
+        // Out of bounds stays put
+        assert_eq!(queue.move_up(5), 5);
+        assert_eq!(queue.move_down(5), 5);
+        assert_eq!(titles(&queue), ["a", "b", "c"]);
+
+        assert_eq!(queue.move_up(2), 1);
+        assert_eq!(titles(&queue), ["a", "c", "b"]);
+        assert_eq!(queue.move_down(0), 1);
+        assert_eq!(titles(&queue), ["c", "a", "b"]);
+
+        let mut empty = Queue::new();
+        assert_eq!(empty.move_up(0), 0);
+        assert_eq!(empty.move_down(0), 0);
+    }
+
+    #[test]
+    fn retain_can_update_in_place() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.retain(|track| {
+            track.playlist_index = 1;
+            track.metadata.title != "b"
+        });
+
+        assert_eq!(titles(&queue), ["a", "c"]);
+        assert!(queue.iter().all(|track| track.playlist_index == 1));
+
+        queue.retain(|_| false);
+        assert!(queue.is_empty());
+    }
+}
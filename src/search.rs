@@ -0,0 +1,165 @@
+/*
+ * Handles fuzzy searching the library
+ */
+
+use std::cmp::Reverse;
+
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+};
+
+use crate::files::{Playlist, SourceHandler, Track};
+
+/// Results shown at once, anything past this is noise anyway
+const MAX_RESULTS: usize = 100;
+
+/*
+ * Structs
+ */
+
+/// What a search entry points to
+#[derive(Clone)]
+pub enum SearchKind {
+    /// Id of the playlist
+    Playlist(usize),
+    /// Boxed as it's far larger than an id
+    Track(Box<Track>),
+}
+
+pub struct SearchEntry {
+    pub kind: SearchKind,
+    /// Text matched against
+    pub text: String,
+}
+
+pub struct SearchResult {
+    /// Index into the search entries
+    pub entry: usize,
+    pub score: i64,
+    /// Char indices of matched characters
+    pub indices: Vec<usize>,
+}
+
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    pub results: Vec<SearchResult>,
+    entries: Vec<SearchEntry>,
+    matcher: SkimMatcherV2,
+}
+
+/*
+ * Functions
+ */
+
+impl Search {
+    /// Reads every playlist & track into the index, only done once
+    pub fn build_index(&mut self, source: &SourceHandler) {
+        if !self.entries.is_empty() {
+            return;
+        }
+
+        let mut playlists: Vec<&Playlist> = source.playlists.values().collect();
+        playlists.sort_by_key(|playlist| playlist.id);
+
+        for playlist in playlists {
+            self.entries.push(SearchEntry {
+                kind: SearchKind::Playlist(playlist.id),
+                text: format!("{} - {}", playlist.artists, playlist.title),
+            });
+
+            for track in playlist.tracks() {
+                self.entries.push(SearchEntry {
+                    text: format!(
+                        "{} · {} · {}",
+                        track.metadata.title, track.metadata.artists, playlist.title
+                    ),
+                    kind: SearchKind::Track(Box::new(track)),
+                });
+            }
+        }
+    }
+
+    /// Drops the index so the next search picks up library changes
+    pub fn clear_index(&mut self) {
+        self.entries.clear();
+        self.results.clear();
+    }
+
+    /*
+     * Query
+     */
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.update();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.update();
+    }
+
+    pub fn reset(&mut self) {
+        self.query.clear();
+        self.results.clear();
+    }
+
+    /// Re-runs the query against the index, best matches first
+    fn update(&mut self) {
+        self.results.clear();
+        if self.query.is_empty() {
+            return;
+        }
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some((score, indices)) = self.matcher.fuzzy_indices(&entry.text, &self.query) {
+                self.results.push(SearchResult {
+                    entry: i,
+                    score,
+                    indices,
+                });
+            }
+        }
+
+        self.results.sort_by_key(|result| Reverse(result.score));
+        self.results.truncate(MAX_RESULTS);
+    }
+
+    /*
+     * Fetchers
+     */
+
+    /// Entry of the result at index
+    pub fn get(&self, index: usize) -> Option<&SearchEntry> {
+        self.entries.get(self.results.get(index)?.entry)
+    }
+
+    /// Lists out results with matched characters highlighted
+    pub fn display(&self) -> Vec<Text<'_>> {
+        let mut result = Vec::new();
+
+        for i in &self.results {
+            let entry = &self.entries[i.entry];
+            let marker = match entry.kind {
+                SearchKind::Playlist(_) => Span::styled("▣ ", Style::new().dim()),
+                SearchKind::Track(_) => Span::styled("♪ ", Style::new().dim()),
+            };
+
+            let mut spans = vec![marker];
+            for (index, c) in entry.text.chars().enumerate() {
+                let style = match i.indices.contains(&index) {
+                    true => Style::new().bold().green(),
+                    false => Style::new(),
+                };
+                spans.push(Span::styled(c.to_string(), style));
+            }
+
+            result.push(Text::from(Line::from(spans)));
+        }
+
+        result
+    }
+}
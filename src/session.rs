@@ -0,0 +1,72 @@
+/*
+ * Handles saving & restoring playback between runs
+ */
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use color_eyre::eyre::Error;
+use serde::{Deserialize, Serialize};
+
+/*
+ * Statics
+ */
+
+/// Session file name, saved next to the config
+pub const SESSION_FILE: &str = "tplayer_session.json";
+
+/// Session path so it can be referenced later
+static SESSION_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/*
+ * Data
+ */
+
+/// Playback state at the time of quitting, tracks are stored by path
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Session {
+    pub current_track: Option<PathBuf>,
+    pub position: Duration,
+    pub primary_track: Option<PathBuf>,
+    pub queue: Vec<PathBuf>,
+
+    /// [`crate::files::Playlist::key`] of the selected playlist, ids change when the library does
+    pub selected_playlist: Option<PathBuf>,
+    pub selected_track: Option<usize>,
+}
+
+impl Session {
+    /*
+     * Init
+     */
+
+    /// Try to parse the session, None if there isn't a usable one
+    pub fn parse(path: &Path) -> Option<Self> {
+        // Save path for later, even without a session to restore
+        let _ = SESSION_PATH.set(path.to_path_buf());
+
+        let file = File::open(path).ok()?;
+        serde_json::from_reader(file).ok()
+    }
+
+    /*
+     * Cleanup
+     */
+
+    /// Saves session to main path
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match SESSION_PATH.get() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let serialized = serde_json::to_string(self)?;
+
+        fs::write(path, serialized)?;
+        Ok(())
+    }
+}
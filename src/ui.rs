@@ -1,19 +1,28 @@
+pub mod album_art;
 pub mod current_playing;
 pub mod list_area;
+pub mod picker;
 pub mod progress;
+pub mod prompt;
+pub mod search;
 pub mod status;
 
 use ratatui::{
     Frame,
-    layout::{Constraint, Layout},
+    layout::{Constraint, Flex, Layout, Rect},
+    widgets::Clear,
 };
 
 use crate::{
-    app::{App, CurrentList},
+    app::{App, CurrentList, PromptKind},
     ui::{
+        album_art::AlbumArt,
         current_playing::CurrentPlaying,
         list_area::ListArea,
+        picker::PickerPopup,
         progress::Progress,
+        prompt::PromptPopup,
+        search::SearchPopup,
         status::{Status, StatusInfo},
     },
 };
@@ -22,14 +31,28 @@ impl App {
     /// Renders the user interface widgets.
     pub fn render(&mut self, frame: &mut Frame) {
         // Required Data
-        let current_playlist = self.album_list_state.selected().unwrap();
+        // Nothing is selected when the library is empty
+        let current_playlist = self
+            .album_list_state
+            .selected()
+            .and_then(|id| self.source.playlists.get(&id));
 
         // Split lists & status
         let vertical_main =
             Layout::vertical([Constraint::Fill(1), Constraint::Length(4)]).split(frame.area());
         // Split Album & Track
-        let horizontal_lists = Layout::horizontal([Constraint::Ratio(1, 3), Constraint::Fill(1)])
-            .split(vertical_main[0]);
+        let horizontal_lists = Layout::horizontal([
+            Constraint::Ratio(1, 3),
+            Constraint::Fill(1),
+            Constraint::Ratio(1, 4),
+        ])
+        .split(vertical_main[0]);
+        // Split Album list & Album art, cells are roughly twice as tall as wide
+        let vertical_albums = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(horizontal_lists[0].width / 2),
+        ])
+        .split(horizontal_lists[0]);
         // Split status into Playing, Progress, Status
         let horizontal_status = Layout::horizontal([
             Constraint::Fill(1),
@@ -44,22 +67,93 @@ impl App {
                 self.source.display_playlists(),
                 self.current_list == CurrentList::Playlists,
             ),
-            horizontal_lists[0],
+            vertical_albums[0],
             &mut self.album_list_state,
         );
+        // Album Art
+        let playing = self.audio.current_track.as_ref().map(|v| &v.track);
+        frame.render_stateful_widget(
+            AlbumArt::new(
+                playing,
+                playing
+                    .and_then(|track| self.source.playlists.get(&track.playlist_index))
+                    .map(|playlist| playlist.path.as_path()),
+            ),
+            vertical_albums[1],
+            &mut self.album_art,
+        );
         // Track List
         frame.render_stateful_widget(
             ListArea::new(
-                self.source
-                    .playlists
-                    .get(&current_playlist)
-                    .unwrap()
-                    .display(),
+                current_playlist
+                    .map(|playlist| playlist.display())
+                    .unwrap_or_default(),
                 self.current_list == CurrentList::Tracks,
             ),
             horizontal_lists[1],
             &mut self.track_list_state,
         );
+        // Queue
+        frame.render_stateful_widget(
+            ListArea::new(
+                self.audio.queue.display(),
+                self.current_list == CurrentList::Queue,
+            ),
+            horizontal_lists[2],
+            &mut self.queue_list_state,
+        );
+
+        // Files with missing tags over the lists
+        if self.current_list == CurrentList::Diagnostics {
+            let popup = centered_popup(vertical_main[0]);
+            frame.render_widget(Clear, popup);
+            frame.render_stateful_widget(
+                ListArea::new(self.source.display_diagnostics(), true),
+                popup,
+                &mut self.diagnostics_list_state,
+            );
+        }
+
+        // Search over the lists
+        if self.current_list == CurrentList::Search {
+            frame.render_stateful_widget(
+                SearchPopup::new(&self.search.query, self.search.display()),
+                centered_popup(vertical_main[0]),
+                &mut self.search_list_state,
+            );
+        }
+
+        // Playlist to add to
+        if self.current_list == CurrentList::Picker {
+            frame.render_stateful_widget(
+                PickerPopup::new("Add to playlist", self.source.display_user_playlists()),
+                centered_popup(vertical_main[0]),
+                &mut self.picker_list_state,
+            );
+        }
+
+        // Playlist name or confirmation
+        if let Some(prompt) = self.prompt.as_ref() {
+            let (title, input, message) = match &prompt.kind {
+                PromptKind::Create => ("New playlist", Some(prompt.input.as_str()), None),
+                PromptKind::Rename(_) => ("Rename playlist", Some(prompt.input.as_str()), None),
+                PromptKind::Delete(path) => (
+                    "Delete playlist",
+                    None,
+                    Some(format!(
+                        "Delete `{}`?",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    )),
+                ),
+                PromptKind::Failed(message) => ("Error", None, Some(message.clone())),
+                PromptKind::Warning(message) => ("Warning", None, Some(message.clone())),
+            };
+
+            frame.render_widget(
+                PromptPopup::new(title, input, message.as_deref(), prompt.error.as_deref()),
+                centered_prompt(vertical_main[0]),
+            );
+        }
 
         // Currently Playing
         frame.render_stateful_widget(
@@ -83,7 +177,33 @@ impl App {
             &mut StatusInfo {
                 volume: self.audio.volume(),
                 queue_len: self.audio.queue.len(),
+                repeat: self.config.repeat,
+                shuffle: self.config.shuffle,
             },
         );
     }
 }
+
+/// Area for a popup in the middle of the given area
+fn centered_popup(area: Rect) -> Rect {
+    let [popup] = Layout::horizontal([Constraint::Percentage(60)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [popup] = Layout::vertical([Constraint::Percentage(80)])
+        .flex(Flex::Center)
+        .areas(popup);
+
+    popup
+}
+
+/// Small area for a single line prompt in the middle of the given area
+fn centered_prompt(area: Rect) -> Rect {
+    let [popup] = Layout::horizontal([Constraint::Percentage(50)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [popup] = Layout::vertical([Constraint::Length(4)])
+        .flex(Flex::Center)
+        .areas(popup);
+
+    popup
+}
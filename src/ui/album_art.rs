@@ -0,0 +1,137 @@
+use std::{
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+use lru::LruCache;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, BorderType, Paragraph, StatefulWidget, Widget},
+};
+use ratatui_image::{StatefulImage, picker::Picker, protocol::StatefulProtocol};
+
+use crate::files::{self, Track};
+
+/// Number of decoded covers kept around
+const ART_CACHE_SIZE: usize = 16;
+/// Number of tracks whose cover source is remembered, far cheaper than a decoded cover
+const SOURCE_CACHE_SIZE: usize = 256;
+
+/// Holds the terminal graphics protocol & decoded covers
+pub struct AlbumArtState {
+    picker: Picker,
+    /// Track path to where its cover comes from, `None` means no art was found so we don't look again
+    sources: LruCache<PathBuf, Option<PathBuf>>,
+    /// Keyed by cover source, the track for embedded art or the folder's cover file
+    cache: LruCache<PathBuf, StatefulProtocol>,
+}
+
+pub struct AlbumArt<'a> {
+    track: Option<&'a Track>,
+    playlist_path: Option<&'a Path>,
+}
+
+impl AlbumArtState {
+    /// Queries the terminal for a graphics protocol (sixel, kitty, iTerm)
+    ///
+    /// Must be called after the terminal is initialized but before the event thread starts
+    /// reading stdin, falls back to halfblocks if the terminal doesn't answer
+    pub fn new() -> Self {
+        let picker = Picker::from_query_stdio().unwrap_or_else(|_| Picker::from_fontsize((8, 16)));
+
+        Self::with_picker(picker)
+    }
+
+    /// Halfblocks without asking the terminal, for drawing off-screen
+    pub fn headless() -> Self {
+        Self::with_picker(Picker::from_fontsize((8, 16)))
+    }
+
+    fn with_picker(picker: Picker) -> Self {
+        Self {
+            picker,
+            sources: LruCache::new(NonZeroUsize::new(SOURCE_CACHE_SIZE).unwrap()),
+            cache: LruCache::new(NonZeroUsize::new(ART_CACHE_SIZE).unwrap()),
+        }
+    }
+
+    /// Fetches the cover for a track, decoding it on a cache miss
+    fn protocol(&mut self, track: &Track, playlist_path: &Path) -> Option<&mut StatefulProtocol> {
+        let source = match self.sources.get(&track.path).cloned() {
+            Some(None) => return None,
+            // Decoded covers may have been evicted before the source was
+            Some(Some(source)) if self.cache.contains(&source) => source,
+            _ => {
+                let source = self.load_cover(&track.path, playlist_path);
+                self.sources.put(track.path.clone(), source.clone());
+                source?
+            }
+        };
+
+        self.cache.get_mut(&source)
+    }
+
+    /// Embedded picture first, then a cover file in the playlist folder
+    ///
+    /// Returns where the cover came from, folder covers are decoded once for the whole album
+    fn load_cover(&mut self, track_path: &Path, playlist_path: &Path) -> Option<PathBuf> {
+        if let Some(image) =
+            files::embedded_cover(track_path).and_then(|data| image::load_from_memory(&data).ok())
+        {
+            let protocol = self.picker.new_resize_protocol(image);
+            self.cache.put(track_path.to_path_buf(), protocol);
+            return Some(track_path.to_path_buf());
+        }
+
+        let file = files::cover_file(playlist_path)?;
+        if !self.cache.contains(&file) {
+            let protocol = self.picker.new_resize_protocol(image::open(&file).ok()?);
+            self.cache.put(file.clone(), protocol);
+        }
+        Some(file)
+    }
+}
+
+/// Doesn't query the terminal, there may not be one
+impl Default for AlbumArtState {
+    fn default() -> Self {
+        Self::headless()
+    }
+}
+
+impl<'a> AlbumArt<'a> {
+    pub fn new(track: Option<&'a Track>, playlist_path: Option<&'a Path>) -> Self {
+        Self {
+            track,
+            playlist_path,
+        }
+    }
+}
+
+impl StatefulWidget for AlbumArt<'_> {
+    type State = AlbumArtState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut AlbumArtState) {
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::new().green());
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        // Find cover if something is playing
+        let protocol = match (self.track, self.playlist_path) {
+            (Some(track), Some(playlist_path)) => state.protocol(track, playlist_path),
+            _ => None,
+        };
+
+        match protocol {
+            Some(protocol) => StatefulImage::default().render(inner, buf, protocol),
+            None => Paragraph::new(Line::styled("No Cover", Style::new().dim().italic()))
+                .centered()
+                .render(inner, buf),
+        }
+    }
+}
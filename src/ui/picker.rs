@@ -0,0 +1,51 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style, Stylize},
+    text::{Line, Text},
+    widgets::{Block, BorderType, Clear, List, ListState, Paragraph, StatefulWidget, Widget},
+};
+
+pub struct PickerPopup<'a> {
+    title: &'a str,
+    items: Vec<Text<'a>>,
+}
+
+impl<'a> PickerPopup<'a> {
+    pub fn new(title: &'a str, items: Vec<Text<'a>>) -> Self {
+        Self { title, items }
+    }
+}
+
+impl StatefulWidget for PickerPopup<'_> {
+    type State = ListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut ListState) {
+        // Drawn on top of the lists
+        Clear.render(area, buf);
+
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Green))
+            .title(format!(" {} ", self.title));
+
+        // Nothing to pick from yet
+        if self.items.is_empty() {
+            Paragraph::new(Line::styled(
+                "No playlists, press n to create one",
+                Style::default().dim().italic(),
+            ))
+            .centered()
+            .block(block)
+            .render(area, buf);
+            return;
+        }
+
+        let list = List::new(self.items)
+            .block(block)
+            .highlight_style(Style::default().reversed().fg(Color::Green).not_dim())
+            .highlight_symbol("|")
+            .repeat_highlight_symbol(true);
+        StatefulWidget::render(list, area, buf, state);
+    }
+}
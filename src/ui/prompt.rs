@@ -0,0 +1,64 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Clear, Paragraph, Widget},
+};
+
+pub struct PromptPopup<'a> {
+    title: &'a str,
+    /// Typed text, `None` when only confirming
+    input: Option<&'a str>,
+    message: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+impl<'a> PromptPopup<'a> {
+    pub fn new(
+        title: &'a str,
+        input: Option<&'a str>,
+        message: Option<&'a str>,
+        error: Option<&'a str>,
+    ) -> Self {
+        Self {
+            title,
+            input,
+            message,
+            error,
+        }
+    }
+}
+
+impl Widget for PromptPopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        // Drawn on top of the lists
+        Clear.render(area, buf);
+
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Green))
+            .title(format!(" {} ", self.title));
+
+        let mut lines = Vec::new();
+        if let Some(message) = self.message {
+            lines.push(Line::from(message));
+        }
+        if let Some(input) = self.input {
+            lines.push(Line::from(vec![
+                Span::styled("> ", Style::default().dim()),
+                Span::styled(input, Style::default().bold()),
+                Span::styled("▏", Style::default().fg(Color::Green)),
+            ]));
+        }
+        match self.error {
+            Some(error) => lines.push(Line::styled(error, Style::default().red())),
+            None => lines.push(Line::styled(
+                "Enter to confirm, Esc to cancel",
+                Style::default().dim().italic(),
+            )),
+        }
+
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+}
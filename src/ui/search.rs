@@ -0,0 +1,56 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, BorderType, Clear, List, ListState, Paragraph, StatefulWidget, Widget},
+};
+
+pub struct SearchPopup<'a> {
+    query: &'a str,
+    results: Vec<Text<'a>>,
+}
+
+impl<'a> SearchPopup<'a> {
+    pub fn new(query: &'a str, results: Vec<Text<'a>>) -> Self {
+        Self { query, results }
+    }
+}
+
+impl StatefulWidget for SearchPopup<'_> {
+    type State = ListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut ListState) {
+        // Drawn on top of the lists
+        Clear.render(area, buf);
+
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Green))
+            .title(" Search ");
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        // Split input & results
+        let vertical = Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).split(inner);
+
+        let input = Paragraph::new(vec![
+            Line::from(vec![
+                Span::styled("/ ", Style::default().dim()),
+                Span::styled(self.query, Style::default().bold()),
+                Span::styled("▏", Style::default().fg(Color::Green)),
+            ]),
+            Line::styled(
+                format!("{} results", self.results.len()),
+                Style::default().dim().italic(),
+            ),
+        ]);
+        input.render(vertical[0], buf);
+
+        let list = List::new(self.results)
+            .highlight_style(Style::default().reversed().fg(Color::Green).not_dim())
+            .highlight_symbol("|")
+            .repeat_highlight_symbol(true);
+        StatefulWidget::render(list, vertical[1], buf, state);
+    }
+}
@@ -6,9 +6,13 @@ use ratatui::{
     widgets::{Block, BorderType, Paragraph, StatefulWidget, Widget},
 };
 
+use crate::config::{Repeat, Shuffle};
+
 pub struct StatusInfo {
     pub volume: f32,
     pub queue_len: usize,
+    pub repeat: Repeat,
+    pub shuffle: Shuffle,
 }
 
 pub struct Status {}
@@ -28,14 +32,24 @@ impl StatefulWidget for Status {
 
         let volume_line = Line::from(vec![
             Span::styled("V: ", Style::default().dim()),
-            Span::styled(format!("{}%", vol), Style::default().bold()),
-        ]);
-        let queue_line = Line::from(vec![
+            Span::styled(
+                format!("{:<5}", format!("{}%", vol)),
+                Style::default().bold(),
+            ),
             Span::styled("Q: ", Style::default().dim()),
             Span::styled(format!("{}", queue), Style::default().bold()),
         ]);
+        let mode_line = Line::from(vec![
+            Span::styled("R: ", Style::default().dim()),
+            Span::styled(
+                format!("{:<5}", state.repeat.label()),
+                Style::default().bold(),
+            ),
+            Span::styled("S: ", Style::default().dim()),
+            Span::styled(state.shuffle.label(), Style::default().bold()),
+        ]);
 
-        let text = Paragraph::new(vec![volume_line, queue_line]).block(
+        let text = Paragraph::new(vec![volume_line, mode_line]).block(
             Block::bordered()
                 .border_type(BorderType::Rounded)
                 .border_style(Style::new().fg(Color::Green)),
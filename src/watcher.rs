@@ -0,0 +1,75 @@
+/*
+ * Handles watching the source directory for changes
+ */
+
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use notify::{EventKind, RecursiveMode, Watcher, event::ModifyKind};
+
+use crate::event::Event;
+
+/// How long things have to be quiet before a rescan, copying an album is a burst of events
+const DEBOUNCE: Duration = Duration::from_millis(750);
+/// Files tplayer writes itself, changes to these shouldn't trigger a rescan
+const OWN_FILE_PREFIX: &str = "tplayer_";
+
+/// Spawns a thread that sends [`Event::SourceChanged`] when files are added, removed or renamed
+pub fn spawn(path: PathBuf, sender: mpsc::Sender<Event>) {
+    thread::spawn(move || {
+        let (tx, rx) = mpsc::channel();
+
+        // Uses inotify on linux
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        if watcher.watch(&path, RecursiveMode::Recursive).is_err() {
+            return;
+        }
+
+        loop {
+            // Wait for something relevant
+            match rx.recv() {
+                Ok(Ok(event)) if is_relevant(&event) => {}
+                Ok(_) => continue,
+                Err(_) => return,
+            }
+
+            // Let the burst settle before rescanning
+            loop {
+                match rx.recv_timeout(DEBOUNCE) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            // App has shut down
+            if sender.send(Event::SourceChanged).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Added, removed, renamed or rewritten files that aren't ours
+fn is_relevant(event: &notify::Event) -> bool {
+    let kind = match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    };
+
+    kind && event.paths.iter().any(|path| {
+        !path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .is_some_and(|v| v.starts_with(OWN_FILE_PREFIX))
+    })
+}